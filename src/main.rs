@@ -1,20 +1,25 @@
+mod scheduler;
 mod space_computation;
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    env, fs, io,
     net::SocketAddr,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    str::FromStr,
     sync::{
-        atomic::{AtomicBool, Ordering}, Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering}, Arc,
         Mutex,
     },
-    thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
 
 use axum::{
+    body::Bytes,
     extract::{
         ws::{Message, Utf8Bytes, WebSocket, WebSocketUpgrade},
-        State,
-    }, http::{Request, Response, StatusCode},
+        Multipart, Query, State,
+    }, http::{header, Request, Response, StatusCode},
     response::IntoResponse,
     routing::{get, post},
     serve,
@@ -23,26 +28,89 @@ use axum::{
 };
 use futures::StreamExt;
 use nalgebra::Vector2;
+use scheduler::{default_worker_count, Scheduler, Task, TaskHandle};
 use serde::Deserialize;
 use serde_json::{json, Value};
-use space_computation::{CollisionType, MovementType, Simulation, SpaceObject};
-use tokio::{net::TcpListener, sync::broadcast};
+use space_computation::{
+    AccelerationField, CircularPath, CollisionType, MovementType, Propagator, Simulation,
+    SimulationError, SimulationEvent, SpaceObject, Wind,
+};
+use tokio::{
+    net::TcpListener,
+    signal,
+    sync::broadcast::{self, error::RecvError},
+};
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
-use tracing::{info, info_span, Span};
+use tracing::{info, info_span, warn, Level, Span};
 use uuid::Uuid;
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
-    let (tx, _) = broadcast::channel(32);
+    // `debug` adds a line per simulation step, see `Simulation::calculate_step`
+    tracing_subscriber::fmt()
+        .with_max_level(env_or("LOG_LEVEL", Level::INFO))
+        .init();
+    let workers = env_or("SIMULATION_WORKERS", default_worker_count());
+    info!("stepping simulations on {} worker threads", workers);
+    let idle_ttl = Duration::from_secs(env_or("POOL_IDLE_TTL_SECS", 600));
+    let shutdown_timeout = Duration::from_secs(env_or("SHUTDOWN_TIMEOUT_SECS", 5));
+    let state = AppState {
+        pools: Arc::new(Mutex::new(HashMap::new())),
+        channels: Arc::new(Mutex::new(HashMap::new())),
+        scheduler: Arc::new(Scheduler::new(workers)),
+        binary_formats: Arc::new(Mutex::new(HashMap::new())),
+        max_objects: env_or("MAX_OBJECTS", 5000),
+        batch_timeout: Duration::from_secs(env_or("BATCH_TIMEOUT_SECS", 30)),
+        batch_max_frames: env_or("BATCH_MAX_FRAMES", 1000),
+        button_press_rate: env_or("BUTTON_PRESS_RATE", 120.0),
+        metrics: Arc::default(),
+        state_dir: env::var("STATE_DIR").ok().map(PathBuf::from),
+        checkpoint_interval: Duration::from_secs(env_or("CHECKPOINT_INTERVAL_SECS", 10)),
+        ping_interval: Duration::from_secs(env_or("PING_INTERVAL_SECS", 30)),
+        pong_timeout: Duration::from_secs(env_or("PONG_TIMEOUT_SECS", 10)),
+        max_wall_time: Duration::from_secs_f64(env_or("MAX_WALL_SECONDS", 86400.0)),
+    };
+    restore_simulations(&state).await;
+    tokio::spawn(reap_idle_pools(state.clone(), idle_ttl));
+    let shutdown_state = state.clone();
+
     let app = Router::new()
         .route("/launch_simulation", post(launch_simulation))
+        .route("/launch_with_inputs", post(launch_with_inputs))
+        .route("/import", post(import))
         .route("/delete_simulation", post(delete_simulation))
+        .route("/validate", post(validate))
+        .route("/run_batch", post(run_batch))
+        .route("/update_params", post(update_params))
+        .route("/seek", post(seek))
+        .route("/pause", post(pause))
+        .route("/resume", post(resume))
+        .route("/step", post(step))
+        .route("/reset", post(reset))
+        .route("/rescale_velocities", post(rescale_velocities))
+        .route("/center_com", post(center_com))
+        .route("/add_orbit", post(add_orbit))
+        .route("/add_objects", post(add_objects))
+        .route("/impulse", post(impulse))
+        .route("/freeze_object", post(freeze_object))
+        .route("/thaw_object", post(thaw_object))
+        .route("/diagnostics", get(diagnostics))
+        .route("/simulation_info", get(simulation_info))
+        .route("/closest_pair", get(closest_pair))
+        .route("/energy_breakdown", get(energy_breakdown))
+        .route("/bound_groups", get(bound_groups))
+        .route("/contact_graph", get(contact_graph))
+        .route("/state_vector", get(get_state_vector).post(set_state_vector))
+        .route("/last_collision_age", get(last_collision_age))
+        .route("/sample_field", post(sample_field))
+        .route("/export_csv", get(export_csv))
+        .route("/group_state", get(group_state))
+        .route("/object", get(object_state))
+        .route("/orbit", get(orbit))
+        .route("/time_to_collision", get(time_to_collision))
+        .route("/metrics", get(metrics))
         .route("/ws", get(ws_handler))
-        .with_state(AppState {
-            pools: Arc::new(Mutex::new(HashMap::new())),
-            tx
-        })
+        .with_state(state)
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(|req: &Request<_>| {
@@ -61,27 +129,293 @@ async fn main() {
     println!("Listening on http://{}", addr);
 
     let listener = TcpListener::bind(addr).await.unwrap();
-    serve(listener, app).await.unwrap();
+    serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(shutdown_state, shutdown_timeout))
+        .await
+        .unwrap();
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn error_response(status: StatusCode, message: impl Into<String>) -> (StatusCode, Json<Value>) {
+    (
+        status,
+        Json(json!({ "status": "error", "message": message.into() })),
+    )
+}
+
+/// Like `error_response`, with the error's `code` so clients can tell categories apart.
+fn simulation_error_response(error: SimulationError) -> (StatusCode, Json<Value>) {
+    let (status, Json(mut body)) = error_response(StatusCode::BAD_REQUEST, error.to_string());
+    body["code"] = json!(error.code());
+    (status, Json(body))
 }
 
 type UserId = String;
 pub struct SimulationExecutionPool {
     pub simulation: Arc<Mutex<Simulation>>,
-    pub thread: JoinHandle<()>,
+    /// Clock for control input, see `ControllableAcceleration`.
+    pub started: Instant,
+    /// Last launch, button press or parameter change, used to reap abandoned pools.
+    pub last_activity: Instant,
+    /// Control input comes from a schedule given at launch, socket input is ignored.
+    pub replaying: bool,
+    /// The simulation and stream settings as launched, restored by `/reset`.
+    pub initial_simulation: Simulation,
+    initial_settings: StreamSettings,
+    pub history: Arc<Mutex<History>>,
+    /// Recent positions of every object, when the launch asked for `emit_trails`.
+    pub trails: Option<Arc<Mutex<Trails>>>,
+    /// Frames and events of this simulation, subscribed to by the owner's socket.
+    pub tx: broadcast::Sender<Message>,
+    pub step_count: Arc<AtomicU64>,
+    pub seek_target: Arc<AtomicU64>,
+    pub paused: Arc<AtomicBool>,
+    pub task: TaskHandle,
     pub stop_flag: Arc<AtomicBool>,
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub pools: Arc<Mutex<HashMap<UserId, SimulationExecutionPool>>>,
-    pub tx: broadcast::Sender<(UserId, String)>,
+    /// Per connected socket, the channel its simulation's frames are sent on.
+    pub channels: Arc<Mutex<HashMap<UserId, broadcast::Sender<Message>>>>,
+    pub scheduler: Arc<Scheduler>,
+    /// Per connected socket, whether the client opted into binary `update_step` frames.
+    pub binary_formats: Arc<Mutex<HashMap<UserId, Arc<AtomicBool>>>>,
+    /// Upper bound on objects per simulation, the force loop is quadratic in their number.
+    pub max_objects: usize,
+    /// Wall-clock budget of a single `/run_batch` request.
+    pub batch_timeout: Duration,
+    /// Most frames a `/run_batch` response may contain.
+    pub batch_max_frames: usize,
+    /// Button presses and thrust updates per second a socket may send, further ones are dropped.
+    pub button_press_rate: f64,
+    pub metrics: Arc<Metrics>,
+    /// Directory simulations are checkpointed to and restored from at startup, persistence is
+    /// off when unset.
+    pub state_dir: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    /// Sockets are pinged this often and closed when no pong arrives within `pong_timeout`, so
+    /// clients lost behind a proxy do not keep their simulation running.
+    pub ping_interval: Duration,
+    pub pong_timeout: Duration,
+    /// Longest a simulation may run in real time, also the cap of a launch's `max_wall_seconds`.
+    pub max_wall_time: Duration,
+}
+
+/// Counters updated by the simulation tasks, served at `/metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    pub steps_total: AtomicU64,
+    pub step_nanos_total: AtomicU64,
+}
+
+/// Removes the pool of `user_id` and waits until its task has run for the last time, so nothing
+/// more is broadcast for it once this returns.
+async fn stop_execution_pool(state: &AppState, user_id: &str) {
+    let pool = state.pools.lock().unwrap().remove(user_id);
+    if let Some(pool) = pool {
+        pool.stop_flag.store(true, Ordering::Relaxed);
+        // The task may be mid-run, which must not hold up the runtime's threads
+        let task = pool.task;
+        let _ = tokio::task::spawn_blocking(move || task.join()).await;
+        if let Some(dir) = &state.state_dir {
+            remove_checkpoint(&checkpoint_path(dir, user_id));
+        }
+    }
+}
+
+/// File name derived from the hex-encoded `user_id`, which is client-chosen.
+fn checkpoint_path(dir: &Path, user_id: &str) -> PathBuf {
+    let name = user_id
+        .bytes()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    dir.join(format!("{}.json", name))
+}
+
+/// Saves `sim` as a `launch_simulation` payload: `payload` with the current parameters and
+/// objects, plus a `checkpoint` entry to resume the clock and step count from.
+fn write_checkpoint(
+    checkpointing: &Checkpointing,
+    sim: &Simulation,
+    step_count: u64,
+) -> io::Result<()> {
+    let mut payload = checkpointing.payload.clone();
+    if let Value::Object(params) = simulation_params_json(sim) {
+        for (key, value) in params {
+            payload[key] = value;
+        }
+    }
+    payload["space_objects"] = json!(
+        sim.space_objects
+            .iter()
+            .map(object_json)
+            .collect::<Vec<_>>()
+    );
+    // Already part of `space_objects`
+    payload["attractors"] = Value::Null;
+    payload["checkpoint"] = json!({ "time": sim.time, "step_count": step_count });
+
+    // Written aside and renamed so that a crash mid-write leaves the previous checkpoint intact
+    let tmp = checkpointing.path.with_extension("tmp");
+    fs::write(&tmp, payload.to_string())?;
+    fs::rename(tmp, &checkpointing.path)
+}
+
+fn remove_checkpoint(path: &Path) {
+    if let Err(e) = fs::remove_file(path)
+        && e.kind() != io::ErrorKind::NotFound
+    {
+        warn!("could not remove checkpoint {}: {}", path.display(), e);
+    }
+}
+
+/// Relaunches every simulation checkpointed in `state_dir`. Their sockets are gone, a client
+/// takes one over by connecting with its `user_id` as `resume`, see `attach_socket`. Ones that
+/// are not taken over are eventually reaped as idle.
+async fn restore_simulations(state: &AppState) {
+    let Some(dir) = &state.state_dir else {
+        return;
+    };
+    if let Err(e) = fs::create_dir_all(dir) {
+        warn!("could not create state directory {}: {}", dir.display(), e);
+        return;
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("could not read state directory {}: {}", dir.display(), e);
+            return;
+        }
+    };
+
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let mut data = match fs::read(&path).map(|bytes| serde_json::from_slice::<Value>(&bytes)) {
+            Ok(Ok(data)) if data.is_object() => data,
+            _ => {
+                warn!("skipping unreadable checkpoint {}", path.display());
+                continue;
+            }
+        };
+        let checkpoint = data["checkpoint"].take();
+        // The objects of a checkpoint were perturbed when first launched
+        data["perturbation"] = Value::Null;
+        let (status, Json(body)) = launch(state, &data, None, Some(&checkpoint)).await;
+        if status.is_success() {
+            info!("restored simulation of {}", data["user_id"]);
+        } else {
+            warn!("could not restore {}: {}", path.display(), body["message"]);
+        }
+    }
+}
+
+/// Stops pools nobody has interacted with for longer than `ttl`, e.g. ones launched over REST
+/// whose socket never connected or disconnected uncleanly. A connected socket watching its
+/// simulation counts as interaction.
+async fn reap_idle_pools(state: AppState, ttl: Duration) {
+    let mut interval = tokio::time::interval(ttl.min(Duration::from_secs(60)));
+    loop {
+        interval.tick().await;
+        let connected = state
+            .channels
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<HashSet<_>>();
+        let idle = state
+            .pools
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(user_id, pool)| {
+                pool.last_activity.elapsed() > ttl && !connected.contains(*user_id)
+            })
+            .map(|(user_id, _)| user_id.clone())
+            .collect::<Vec<_>>();
+        for user_id in idle {
+            info!("stopping idle simulation of {}", user_id);
+            stop_execution_pool(&state, &user_id).await;
+        }
+    }
 }
 
-fn stop_execution_pool(state: &AppState, user_id: &str) {
-    let mut map = state.pools.lock().unwrap();
-    if let Some(pool) = map.remove(user_id) {
+/// Resolves on SIGINT or SIGTERM once every pool has been stopped, waiting at most `timeout` for
+/// their final runs to finish.
+async fn shutdown_signal(state: AppState, timeout: Duration) {
+    let ctrl_c = async {
+        signal::ctrl_c().await.unwrap();
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .unwrap()
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    info!("shutting down, stopping all simulations");
+
+    let pools = state.pools.lock().unwrap().drain().collect::<Vec<_>>();
+    for (_, pool) in &pools {
         pool.stop_flag.store(true, Ordering::Relaxed);
-        let _ = pool.thread.join();
+    }
+    let _ = tokio::task::spawn_blocking(move || {
+        let deadline = Instant::now() + timeout;
+        for (user_id, pool) in pools {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !pool.task.join_timeout(remaining) {
+                warn!("simulation of {} did not stop in time", user_id);
+            }
+        }
+    })
+    .await;
+}
+
+/// Token bucket holding up to one second worth of tokens, refilled continuously at `rate` per
+/// second.
+struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            tokens: rate,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.rate);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
     }
 }
 
@@ -92,67 +426,332 @@ struct ButtonPress {
 }
 
 fn handle_button_press(state: &AppState, user_id: &str, press: ButtonPress) {
-    if let Some(pool) = state.pools.lock().unwrap().get_mut(user_id) {
-        if let Some(acc) = pool
+    if let Some(pool) = state.pools.lock().unwrap().get_mut(user_id)
+        && !pool.replaying
+        && let Some(acc) = pool
             .simulation
             .lock()
             .unwrap()
             .controllable_acceleration
             .as_mut()
-        {
-            match press.direction.as_str() {
-                "up" => acc.up = press.is_pressed,
-                "down" => acc.down = press.is_pressed,
-                "left" => acc.left = press.is_pressed,
-                "right" => acc.right = press.is_pressed,
-                _ => {}
+    {
+        pool.last_activity = Instant::now();
+        acc.set(
+            &press.direction,
+            press.is_pressed,
+            pool.started.elapsed().as_secs_f64(),
+        );
+    }
+}
+
+/// Analog counterpart of `button_press` for gamepad clients.
+fn handle_thrust(state: &AppState, user_id: &str, data: &Value) {
+    let thrust = Vector2::new(
+        data["x"].as_f64().unwrap_or(0.0),
+        data["y"].as_f64().unwrap_or(0.0),
+    );
+    if let Some(pool) = state.pools.lock().unwrap().get_mut(user_id)
+        && !pool.replaying
+        && let Some(acc) = pool
+            .simulation
+            .lock()
+            .unwrap()
+            .controllable_acceleration
+            .as_mut()
+        && acc
+            .set_thrust(thrust, pool.started.elapsed().as_secs_f64())
+            .is_ok()
+    {
+        pool.last_activity = Instant::now();
+    }
+}
+
+fn handle_set_format(binary_format: &AtomicBool, data: &Value) {
+    match data["format"].as_str() {
+        Some("binary") => binary_format.store(true, Ordering::Relaxed),
+        Some("json") => binary_format.store(false, Ordering::Relaxed),
+        _ => {}
+    }
+}
+
+fn handle_client_event(
+    state: &AppState,
+    user_id: &str,
+    binary_format: &AtomicBool,
+    input_limiter: &mut RateLimiter,
+    val: &Value,
+) {
+    match val["event"].as_str() {
+        Some("button_press") => {
+            if input_limiter.try_acquire()
+                && let Ok(press) = serde_json::from_value::<ButtonPress>(val["data"].clone())
+            {
+                handle_button_press(state, user_id, press);
+            }
+        }
+        Some("thrust") => {
+            if input_limiter.try_acquire() {
+                handle_thrust(state, user_id, &val["data"]);
             }
         }
+        Some("set_format") => handle_set_format(binary_format, &val["data"]),
+        _ => {}
     }
 }
 
-async fn handle_socket(mut socket: WebSocket, state: AppState) {
+fn text_message(payload: &Value) -> Message {
+    Message::Text(Utf8Bytes::from(payload.to_string()))
+}
+
+/// Registers a new socket under a fresh `user_id`, or under `resume` if that names a simulation
+/// no socket is attached to, e.g. one restored from a checkpoint. Returns the `user_id`, the
+/// channel to forward to the socket and the socket's frame format.
+fn attach_socket(
+    state: &AppState,
+    resume: Option<&str>,
+) -> (UserId, broadcast::Receiver<Message>, Arc<AtomicBool>) {
+    if let Some(user_id) = resume {
+        let pool = state.pools.lock().unwrap().get(user_id).map(|pool| {
+            (
+                pool.tx.clone(),
+                Arc::clone(&pool.initial_settings.binary_format),
+            )
+        });
+        if let Some((tx, binary_format)) = pool
+            && let Entry::Vacant(entry) = state.channels.lock().unwrap().entry(user_id.to_owned())
+        {
+            let rx = tx.subscribe();
+            entry.insert(tx);
+            state
+                .binary_formats
+                .lock()
+                .unwrap()
+                .insert(user_id.to_owned(), Arc::clone(&binary_format));
+            info!("socket resumed simulation of {}", user_id);
+            return (user_id.to_owned(), rx, binary_format);
+        }
+    }
+
     let user_id = Uuid::new_v4().to_string();
+    let binary_format = Arc::new(AtomicBool::new(false));
+    state
+        .binary_formats
+        .lock()
+        .unwrap()
+        .insert(user_id.clone(), Arc::clone(&binary_format));
+    let (tx, rx) = broadcast::channel(32);
+    state.channels.lock().unwrap().insert(user_id.clone(), tx);
+    (user_id, rx, binary_format)
+}
+
+/// Next message for the socket of `user_id`, `None` once the channel is closed. A slow client
+/// misses frames rather than holding the simulation back.
+async fn next_message(rx: &mut broadcast::Receiver<Message>, user_id: &str) -> Option<Message> {
+    loop {
+        match rx.recv().await {
+            Ok(msg) => return Some(msg),
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("socket of {} lagged, skipped {} messages", user_id, skipped);
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+async fn handle_socket(mut socket: WebSocket, state: AppState, resume: Option<String>) {
+    let (user_id, mut rx, binary_format) = attach_socket(&state, resume.as_deref());
     let _ = socket
-        .send(Message::Text(Utf8Bytes::from(
-            json!({ "user_id": &user_id }).to_string(),
-        )))
+        .send(text_message(&json!({ "user_id": &user_id })))
         .await;
-    let mut rx = state.tx.subscribe();
+    let mut input_limiter = RateLimiter::new(state.button_press_rate);
+    let mut ping = tokio::time::interval_at(
+        tokio::time::Instant::now() + state.ping_interval,
+        state.ping_interval,
+    );
+    let mut pong_deadline = None;
     loop {
         tokio::select! {
-            Ok((uid, payload)) = rx.recv() => {
-                if uid == user_id {
-                    let _ = socket.send(Message::Text(Utf8Bytes::from(payload))).await;
+            received = next_message(&mut rx, &user_id) => match received {
+                Some(msg) => {
+                    let _ = socket.send(msg).await;
                 }
+                None => break,
+            },
+            _ = ping.tick() => {
+                let _ = socket.send(Message::Ping(Bytes::new())).await;
+                pong_deadline.get_or_insert(tokio::time::Instant::now() + state.pong_timeout);
             },
-            Some(Ok(msg)) = socket.next() => {
-                if let Message::Text(txt) = msg {
+            _ = tokio::time::sleep_until(pong_deadline.unwrap_or_else(tokio::time::Instant::now)),
+                if pong_deadline.is_some() =>
+            {
+                info!("socket of {} missed its pong, closing", user_id);
+                break;
+            },
+            msg = socket.next() => match msg {
+                Some(Ok(Message::Pong(_))) => pong_deadline = None,
+                Some(Ok(Message::Text(txt))) => {
                     if let Ok(val) = serde_json::from_str::<Value>(&txt) {
-                        if val.get("event") == Some(&Value::String("button_press".into())) {
-                            if let Ok(press) = serde_json::from_value::<ButtonPress>(val["data"].clone()) {
-                                handle_button_press(&state, &user_id, press);
-                            }
-                        }
+                        handle_client_event(&state, &user_id, &binary_format, &mut input_limiter, &val);
                     }
                 }
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => break,
             },
-            else => break,
         }
     }
-    stop_execution_pool(&state, &user_id);
+    stop_execution_pool(&state, &user_id).await;
+    state.binary_formats.lock().unwrap().remove(&user_id);
+    state.channels.lock().unwrap().remove(&user_id);
 }
 
-async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+#[derive(Deserialize)]
+struct SocketQuery {
+    /// `user_id` of a simulation to take over, see `attach_socket`.
+    resume: Option<String>,
 }
 
-async fn launch_simulation(
+async fn ws_handler(
+    ws: WebSocketUpgrade,
     State(state): State<AppState>,
-    Json(data): Json<Value>,
+    Query(query): Query<SocketQuery>,
 ) -> impl IntoResponse {
-    let user_id = data["user_id"].as_str().unwrap_or_default().to_owned();
-    stop_execution_pool(&state, &user_id);
+    ws.on_upgrade(move |socket| handle_socket(socket, state, query.resume))
+}
+
+/// Parses the optional `field` object of `{"ax": "...", "ay": "..."}` expressions, a missing
+/// component is zero.
+fn parse_field(field: &Value) -> Result<Option<AccelerationField>, SimulationError> {
+    if field.is_null() {
+        return Ok(None);
+    }
+    let ax = field["ax"].as_str().unwrap_or("0");
+    let ay = field["ay"].as_str().unwrap_or("0");
+    AccelerationField::new(ax, ay).map(Some)
+}
+
+fn parse_wind(wind: &Value) -> Option<Wind> {
+    if wind.is_null() {
+        return None;
+    }
+    Some(Wind {
+        amplitude: wind["amplitude"].as_f64().unwrap_or(0.0),
+        frequency: wind["frequency"].as_f64().unwrap_or(0.0),
+        direction: Vector2::new(
+            wind["direction"]["x"].as_f64().unwrap_or(1.0),
+            wind["direction"]["y"].as_f64().unwrap_or(0.0),
+        ),
+    })
+}
+
+/// Keyframes given as `{ "time": t, "elasticity": e }` entries.
+fn parse_elasticity_schedule(schedule: &Value) -> Vec<(f64, f64)> {
+    schedule
+        .as_array()
+        .map_or(&[][..], |v| v.as_slice())
+        .iter()
+        .map(|keyframe| {
+            (
+                keyframe["time"].as_f64().unwrap_or(0.0),
+                // Rejected by the range check, a keyframe needs an elasticity
+                keyframe["elasticity"].as_f64().unwrap_or(f64::NAN),
+            )
+        })
+        .collect()
+}
+
+/// Attractors given in bulk as `[x, y, mass]` or `[x, y, mass, radius]` entries, named
+/// `attractor_<n>`.
+fn parse_attractors(attractors: &[Value]) -> Result<Vec<SpaceObject>, SimulationError> {
+    attractors
+        .iter()
+        .enumerate()
+        .map(|(i, a)| {
+            let (x, y, mass) = match (a[0].as_f64(), a[1].as_f64(), a[2].as_f64()) {
+                (Some(x), Some(y), Some(mass)) => (x, y, mass),
+                _ => return Err(SimulationError::MalformedAttractor(i)),
+            };
+            SpaceObject::new(
+                format!("attractor_{}", i),
+                mass,
+                a[3].as_f64().unwrap_or(1.0),
+                Vector2::new(x, y),
+                Vector2::zeros(),
+                MovementType::Attractor,
+                0.0,
+            )
+        })
+        .collect()
+}
+
+fn parse_space_object(o: &Value) -> Result<SpaceObject, SimulationError> {
+    let pos = Vector2::new(
+        o["position"]["x"].as_f64().unwrap_or(0.0),
+        o["position"]["y"].as_f64().unwrap_or(0.0),
+    );
+    let vel = Vector2::new(
+        o["velocity"]["x"].as_f64().unwrap_or(0.0),
+        o["velocity"]["y"].as_f64().unwrap_or(0.0),
+    );
+    let mv = MovementType::try_from(o["movement_type"].as_i64().unwrap_or(0))
+        .unwrap_or(MovementType::Static);
+
+    let mut obj = SpaceObject::new(
+        o["name"].as_str().unwrap_or("Unnamed"),
+        o["mass"].as_f64().unwrap_or(1.0),
+        o["radius"].as_f64().unwrap_or(1.0),
+        pos,
+        vel,
+        mv,
+        o["softening"].as_f64().unwrap_or(0.0),
+    )?;
+    obj.group = o["group"].as_str().map(str::to_owned);
+    obj.temperature = o["temperature"].as_f64().unwrap_or(0.0).max(0.0);
+    obj.age = o["age"].as_f64().unwrap_or(0.0).max(0.0);
+    obj.color = o["color"].as_str().map(str::to_owned);
+    obj.shape = o["shape"].as_str().map(str::to_owned);
+    obj.inside_roche_limit = o["inside_roche_limit"].as_bool().unwrap_or(false);
+    obj.collision_mask = match o["collision_mask"].as_u64().unwrap_or(0).try_into() {
+        Ok(mask) => mask,
+        Err(_) => return Err(SimulationError::CollisionMaskTooWide(obj.name)),
+    };
+    obj = obj.with_lifetime(o["lifetime"].as_f64())?;
+    if o["frozen"].is_object() {
+        let frozen = &o["frozen"];
+        let movement_type = MovementType::try_from(frozen["movement_type"].as_i64().unwrap_or(1))
+            .unwrap_or(MovementType::Ordinary);
+        let velocity = Vector2::new(
+            frozen["velocity"]["x"].as_f64().unwrap_or(0.0),
+            frozen["velocity"]["y"].as_f64().unwrap_or(0.0),
+        );
+        obj.frozen = Some((movement_type, velocity));
+    }
+    if o["semi_axes"].is_object() {
+        obj = obj.with_semi_axes(Some(Vector2::new(
+            o["semi_axes"]["x"].as_f64().unwrap_or(f64::NAN),
+            o["semi_axes"]["y"].as_f64().unwrap_or(f64::NAN),
+        )))?;
+    }
+    if o["path"].is_object() {
+        let path = &o["path"];
+        obj = obj.with_path(CircularPath {
+            center: Vector2::new(
+                path["center"]["x"].as_f64().unwrap_or(0.0),
+                path["center"]["y"].as_f64().unwrap_or(0.0),
+            ),
+            radius: path["radius"].as_f64().unwrap_or(0.0),
+            angular_velocity: path["angular_velocity"].as_f64().unwrap_or(0.0),
+            phase: path["phase"].as_f64().unwrap_or(0.0),
+        })?;
+    }
+    Ok(obj)
+}
+
+/// Builds the simulation and stream settings described by a `launch_simulation` payload, applying
+/// defaults for missing fields.
+fn parse_launch_payload(
+    data: &Value,
+    max_objects: usize,
+) -> Result<(Simulation, StreamSettings), SimulationError> {
     let s = Simulation::default();
     let time_delta = data["time_delta"].as_f64().unwrap_or(s.time_delta);
     let sim_time = data["simulation_time"]
@@ -169,139 +768,2392 @@ async fn launch_simulation(
         .as_i64()
         .and_then(|v| CollisionType::try_from(v).ok())
         .unwrap_or(s.collision_type);
+    let propagator = data["propagator"]
+        .as_i64()
+        .and_then(|v| Propagator::try_from(v).ok())
+        .unwrap_or(s.propagator);
+    let friction = data["friction_coefficient"]
+        .as_f64()
+        .unwrap_or(s.friction_coefficient);
+    let escape_radius = data["escape_radius"].as_f64();
+    let speed_of_light = data["speed_of_light"].as_f64();
+    let max_speed_fraction = data["max_speed_fraction"]
+        .as_f64()
+        .unwrap_or(s.max_speed_fraction);
+
+    let settings = StreamSettings::from_payload(data)?;
 
-    let objs = data["space_objects"]
+    let raw_objs = data["space_objects"]
         .as_array()
-        .unwrap_or(&vec![])
+        .map_or(&[][..], |v| v.as_slice());
+    let raw_attractors = data["attractors"]
+        .as_array()
+        .map_or(&[][..], |v| v.as_slice());
+    if raw_objs.len() + raw_attractors.len() > max_objects {
+        return Err(SimulationError::TooManyObjects {
+            count: raw_objs.len() + raw_attractors.len(),
+            max: max_objects,
+        });
+    }
+    let mut objs = raw_objs
         .iter()
-        .map(|o| {
-            let pos = Vector2::new(
-                o["position"]["x"].as_f64().unwrap_or(0.0),
-                o["position"]["y"].as_f64().unwrap_or(0.0),
-            );
-            let vel = Vector2::new(
-                o["velocity"]["x"].as_f64().unwrap_or(0.0),
-                o["velocity"]["y"].as_f64().unwrap_or(0.0),
-            );
-            let mv = MovementType::try_from(o["movement_type"].as_i64().unwrap_or(0))
-                .unwrap_or(MovementType::Static);
-
-            SpaceObject {
-                name: o["name"].as_str().unwrap_or("Unnamed").into(),
-                mass: o["mass"].as_f64().unwrap_or(1.0),
-                radius: o["radius"].as_f64().unwrap_or(1.0),
-                position: pos,
-                velocity: vel,
-                acceleration: Vector2::new(0.0, 0.0),
-                movement_type: mv,
-            }
-        })
-        .collect::<Vec<_>>();
+        .map(parse_space_object)
+        .collect::<Result<Vec<_>, _>>()?;
+    objs.extend(parse_attractors(raw_attractors)?);
 
-    let simulation = match Simulation::new(
+    let mut simulation = Simulation::new(
         objs, time_delta, sim_time, g, collision, accel_rate, elasticity,
-    ) {
-        Ok(s) => Arc::new(Mutex::new(s)),
-        Err(msg) => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(json!({ "status": "error", "message": msg })),
-            );
+    )?
+    .with_overlap_allowed(data["allow_overlap"].as_bool().unwrap_or(false))?
+    .with_friction_coefficient(friction)?
+    .with_attractive(data["attractive"].as_bool().unwrap_or(s.attractive))?
+    .with_force_exponent(data["force_exponent"].as_f64().unwrap_or(s.force_exponent))?
+    .with_propagator(propagator)?
+    .with_escape_radius(escape_radius)?
+    .with_speed_of_light(speed_of_light, max_speed_fraction)?
+    .with_max_speed(data["max_speed"].as_f64())?
+    .with_max_acceleration(data["max_acceleration"].as_f64())?
+    .with_field(parse_field(&data["field"])?)
+    .with_wind(parse_wind(&data["wind"]))?
+    .with_elasticity_schedule(parse_elasticity_schedule(&data["elasticity_schedule"]))?
+    .with_time_scale(data["time_scale"].as_f64().unwrap_or(s.time_scale))?
+    .with_cooling_rate(data["cooling_rate"].as_f64().unwrap_or(s.cooling_rate))?
+    .with_substeps(data["substeps"].as_u64().map_or(s.substeps, |n| n as usize))?
+    .with_tidal_disruption(
+        data["tidal_disruption"]
+            .as_bool()
+            .unwrap_or(false)
+            .then(|| data["min_fragment_mass"].as_f64().unwrap_or(0.0)),
+    )?
+    .with_seed(data["seed"].as_u64().unwrap_or(s.seed))
+    .with_max_objects(max_objects)
+    .with_perturbation(data["perturbation"].as_f64())?
+    // After the perturbation, which may move objects off the line
+    .with_dimensions(data["dimensions"].as_u64().unwrap_or(2))?
+    .with_shatter(
+        data["shatter_threshold"].as_f64(),
+        data["shatter_fragments"]
+            .as_u64()
+            .map_or(s.shatter_fragments, |n| n as usize),
+    )?
+    .with_units(
+        data["length_unit"].as_str().map(str::to_owned),
+        data["meters_per_unit"].as_f64(),
+    )?;
+    simulation.debug_energy_check = data["debug_energy_check"].as_bool().unwrap_or(false);
+    simulation.record_collisions = data["emit_collisions"].as_bool().unwrap_or(false);
+    // The builders above may have changed the forces `Simulation::new` primed with
+    simulation.prime();
+
+    Ok((simulation, settings))
+}
+
+async fn launch_simulation(
+    State(state): State<AppState>,
+    Json(data): Json<Value>,
+) -> impl IntoResponse {
+    launch(&state, &data, None, None).await
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ScheduledInput {
+    step: u64,
+    direction: String,
+    is_pressed: bool,
+}
+
+/// Like `launch_simulation`, but the controllable object is driven by the `inputs` schedule of
+/// button presses instead of the socket. Presses are applied at the given step and thrust is
+/// measured in simulated time, so the same schedule always yields the same trajectory.
+async fn launch_with_inputs(
+    State(state): State<AppState>,
+    Json(data): Json<Value>,
+) -> impl IntoResponse {
+    let mut inputs = match serde_json::from_value::<Vec<ScheduledInput>>(data["inputs"].clone()) {
+        Ok(inputs) => inputs,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, format!("Invalid inputs: {}", e)),
+    };
+    if let Some(input) = inputs
+        .iter()
+        .find(|i| !["up", "down", "left", "right"].contains(&i.direction.as_str()))
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("Unknown direction {}", input.direction),
+        );
+    }
+    inputs.sort_by_key(|i| i.step);
+    launch(&state, &data, Some(inputs.into()), None).await
+}
+
+/// Launches a simulation saved as a `launch_simulation` payload, uploaded as the `file` field of a
+/// multipart form. An optional `user_id` field overrides the one in the file.
+async fn import(State(state): State<AppState>, mut multipart: Multipart) -> impl IntoResponse {
+    let mut file = None;
+    let mut user_id = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, e.body_text()),
+        };
+        let name = field.name().map(str::to_owned);
+        let bytes = match field.bytes().await {
+            Ok(bytes) => bytes,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, e.body_text()),
+        };
+        match name.as_deref() {
+            Some("file") => file = Some(bytes),
+            Some("user_id") => user_id = Some(String::from_utf8_lossy(&bytes).into_owned()),
+            _ => {}
         }
+    }
+
+    let Some(file) = file else {
+        return error_response(StatusCode::BAD_REQUEST, "Missing file field");
+    };
+    let mut data = match serde_json::from_slice::<Value>(&file) {
+        Ok(data) => data,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, format!("Malformed JSON: {}", e)),
     };
+    if !data.is_object() {
+        return error_response(StatusCode::BAD_REQUEST, "Simulation must be a JSON object");
+    }
+    if let Some(user_id) = user_id {
+        data["user_id"] = json!(user_id);
+    }
+    launch(&state, &data, None, None).await
+}
 
-    let stop_flag = Arc::new(AtomicBool::new(false));
-    let flag_clone = Arc::clone(&stop_flag);
-    let sim_clone = Arc::clone(&simulation);
-    let uid_clone = user_id.clone();
-    let tx_clone = state.tx.clone();
+/// Replaces the pool of the payload's `user_id` with a new simulation, see `launch_simulation`.
+/// A restored simulation resumes the clock and step count of its `checkpoint`, which clients
+/// cannot set.
+async fn launch(
+    state: &AppState,
+    data: &Value,
+    input_schedule: Option<VecDeque<ScheduledInput>>,
+    checkpoint: Option<&Value>,
+) -> (StatusCode, Json<Value>) {
+    let user_id = data["user_id"].as_str().unwrap_or_default().to_owned();
+    stop_execution_pool(state, &user_id).await;
+
+    let (simulation, mut settings) = match parse_launch_payload(data, state.max_objects) {
+        Ok(parsed) => parsed,
+        Err(e) => return simulation_error_response(e),
+    };
+    if input_schedule.is_some() && simulation.controllable_acceleration.is_none() {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "An input schedule needs a controllable object",
+        );
+    }
+    let mut simulation = simulation;
+    if let Some(checkpoint) = checkpoint {
+        if let Some(time) = checkpoint["time"].as_f64() {
+            simulation.time = time;
+        }
+        settings.step_count.store(
+            checkpoint["step_count"].as_u64().unwrap_or(0),
+            Ordering::Relaxed,
+        );
+    }
+    // Replays are driven by their schedule, which is not part of a checkpoint
+    if let Some(dir) = &state.state_dir
+        && input_schedule.is_none()
+    {
+        let checkpointing = Checkpointing {
+            path: checkpoint_path(dir, &user_id),
+            interval: state.checkpoint_interval,
+            payload: data.clone(),
+        };
+        let step_count = settings.step_count.load(Ordering::Relaxed);
+        if let Err(e) = write_checkpoint(&checkpointing, &simulation, step_count) {
+            warn!("could not checkpoint simulation of {}: {}", user_id, e);
+        }
+        settings.checkpointing = Some(checkpointing);
+    }
+    let replaying = input_schedule.is_some();
+    settings.input_schedule = input_schedule;
+    settings.max_wall_time = settings
+        .max_wall_time
+        .map_or(state.max_wall_time, |time| time.min(state.max_wall_time))
+        .into();
+    if let Some(binary_format) = state.binary_formats.lock().unwrap().get(&user_id) {
+        settings.binary_format = Arc::clone(binary_format);
+    }
+    // Simulations launched without a socket, e.g. restored ones, stream to nobody until then
+    let tx = state
+        .channels
+        .lock()
+        .unwrap()
+        .get(&user_id)
+        .cloned()
+        .unwrap_or_else(|| broadcast::channel(32).0);
+
+    let parameters = simulation_params_json(&simulation);
+    let space_objects = simulation
+        .space_objects
+        .iter()
+        .map(object_json)
+        .collect::<Vec<_>>();
+
+    let pool = spawn_pool(state, &user_id, simulation, settings, tx, replaying);
+    // A concurrent launch for the same user may have got in since the old pool was stopped
+    if let Some(displaced) = state.pools.lock().unwrap().insert(user_id.clone(), pool) {
+        displaced.stop_flag.store(true, Ordering::Relaxed);
+    }
+    (
+        StatusCode::OK,
+        Json(json!({
+            "status": "success",
+            "user_id": user_id,
+            "parameters": parameters,
+            "space_objects": space_objects,
+        })),
+    )
+}
 
-    let thread = thread::spawn(move || {
-        simulate_loop(uid_clone, sim_clone, flag_clone, tx_clone);
-    });
+/// Starts stepping `simulation` for `user_id`, keeping a copy of it and `settings` for `/reset`.
+fn spawn_pool(
+    state: &AppState,
+    user_id: &str,
+    simulation: Simulation,
+    settings: StreamSettings,
+    tx: broadcast::Sender<Message>,
+    replaying: bool,
+) -> SimulationExecutionPool {
+    let initial_simulation = simulation.clone();
+    let initial_settings = settings.clone();
+    let simulation = Arc::new(Mutex::new(simulation));
+    let history = Arc::clone(&settings.history);
+    let trails = settings.trails.clone();
+    let step_count = Arc::clone(&settings.step_count);
+    let seek_target = Arc::clone(&settings.seek_target);
+    let paused = Arc::clone(&settings.paused);
+    let stop_flag = Arc::new(AtomicBool::new(false));
+    let started = Instant::now();
+    let task = state.scheduler.spawn(simulate_loop(
+        user_id.to_owned(),
+        Arc::clone(&simulation),
+        started,
+        Arc::clone(&stop_flag),
+        tx.clone(),
+        Arc::clone(&state.metrics),
+        settings,
+    ));
 
-    let pool = SimulationExecutionPool {
+    SimulationExecutionPool {
         simulation,
+        started,
+        history,
+        trails,
+        tx,
+        step_count,
+        seek_target,
+        paused,
+        last_activity: started,
+        replaying,
+        initial_simulation,
+        initial_settings,
         stop_flag,
-        thread,
+        task,
+    }
+}
+
+/// Restarts the simulation of `user_id` from the objects and parameters it was launched with.
+/// Clients are sent a `reset` event first, so they can drop trails of the old run.
+async fn reset(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let Some(pool) = state.pools.lock().unwrap().remove(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
     };
+    pool.stop_flag.store(true, Ordering::Relaxed);
+    let task = pool.task;
+    let _ = tokio::task::spawn_blocking(move || task.join()).await;
 
-    state.pools.lock().unwrap().insert(user_id, pool);
+    let simulation = pool.initial_simulation;
+    let settings = pool.initial_settings;
+    // The counters, history and trails are shared with the stopped task, which no longer
+    // touches them
+    settings.step_count.store(0, Ordering::Relaxed);
+    settings.seek_target.store(0, Ordering::Relaxed);
+    settings.history.lock().unwrap().clear();
+    if let Some(trails) = &settings.trails {
+        trails.lock().unwrap().clear();
+    }
+    if let Some(checkpointing) = &settings.checkpointing
+        && let Err(e) = write_checkpoint(checkpointing, &simulation, 0)
+    {
+        warn!("could not checkpoint simulation of {}: {}", user_id, e);
+    }
+
+    // Relaunched while the old task was finishing, the launch wins over the reset
+    let mut pools = state.pools.lock().unwrap();
+    let Entry::Vacant(entry) = pools.entry(user_id.to_owned()) else {
+        return error_response(StatusCode::CONFLICT, "Simulation was relaunched");
+    };
+    let _ = pool.tx.send(text_message(&json!({ "event": "reset" })));
+    entry.insert(spawn_pool(
+        &state,
+        user_id,
+        simulation,
+        settings,
+        pool.tx,
+        pool.replaying,
+    ));
     (StatusCode::OK, Json(json!({ "status": "success" })))
 }
 
+/// Parameters of `sim` in the shape of the `launch_simulation` payload.
+fn simulation_params_json(sim: &Simulation) -> Value {
+    json!({
+        "time_delta": sim.time_delta,
+        "simulation_time": sim.simulation_time,
+        "G": sim.g,
+        "force_exponent": sim.force_exponent,
+        "attractive": sim.attractive,
+        "collision_type": sim.collision_type as i64,
+        "acceleration_rate": sim.acceleration_rate,
+        "elasticity_coefficient": sim.elasticity_coefficient,
+        "elasticity_schedule": sim
+            .elasticity_schedule
+            .iter()
+            .map(|&(time, elasticity)| json!({ "time": time, "elasticity": elasticity }))
+            .collect::<Vec<_>>(),
+        "friction_coefficient": sim.friction_coefficient,
+        "propagator": sim.propagator as i64,
+        "escape_radius": sim.escape_radius,
+        "speed_of_light": sim.speed_of_light,
+        "max_speed_fraction": sim.max_speed_fraction,
+        "max_speed": sim.max_speed,
+        "max_acceleration": sim.max_acceleration,
+        "field": sim.field.as_ref().map(|field| json!({
+            "ax": field.ax_source(),
+            "ay": field.ay_source(),
+        })),
+        "wind": sim.wind.map(|wind| json!({
+            "amplitude": wind.amplitude,
+            "frequency": wind.frequency,
+            "direction": { "x": wind.direction.x, "y": wind.direction.y },
+        })),
+        "substeps": sim.substeps,
+        "time_scale": sim.time_scale,
+        "cooling_rate": sim.cooling_rate,
+        "tidal_disruption": sim.min_fragment_mass.is_some(),
+        "min_fragment_mass": sim.min_fragment_mass,
+        "shatter_threshold": sim.shatter_threshold,
+        "shatter_fragments": sim.shatter_fragments,
+        "seed": sim.seed,
+        "dimensions": if sim.one_dimensional { 1 } else { 2 },
+        "length_unit": sim.length_unit,
+        "meters_per_unit": sim.meters_per_unit,
+    })
+}
+
+/// `obj` in the shape of a `space_objects` entry of the `launch_simulation` payload.
+fn object_json(obj: &SpaceObject) -> Value {
+    json!({
+        "name": obj.name,
+        "mass": obj.mass,
+        "radius": obj.radius,
+        "position": { "x": obj.position.x, "y": obj.position.y },
+        "velocity": { "x": obj.velocity.x, "y": obj.velocity.y },
+        "movement_type": obj.movement_type as i64,
+        "group": obj.group,
+        "softening": obj.softening,
+        "temperature": obj.temperature,
+        "lifetime": obj.lifetime,
+        "age": obj.age,
+        "color": obj.color,
+        "shape": obj.shape,
+        "frozen": obj.frozen.map(|(movement_type, velocity)| json!({
+            "movement_type": movement_type as i64,
+            "velocity": { "x": velocity.x, "y": velocity.y },
+        })),
+        "semi_axes": obj.semi_axes.map(|axes| json!({ "x": axes.x, "y": axes.y })),
+        "collision_mask": obj.collision_mask,
+        "inside_roche_limit": obj.inside_roche_limit,
+        "path": obj.path.map(|path| json!({
+            "center": { "x": path.center.x, "y": path.center.y },
+            "radius": path.radius,
+            "angular_velocity": path.angular_velocity,
+            "phase": path.phase,
+        })),
+    })
+}
+
+/// Runs the `launch_simulation` validation without starting anything.
+async fn validate(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    match parse_launch_payload(&data, state.max_objects) {
+        Ok(_) => Json(json!({ "valid": true })),
+        Err(e) => Json(json!({ "valid": false, "message": e.to_string(), "code": e.code() })),
+    }
+}
+
+/// Steps `sim` to the end, keeping every `downsample`-th frame. Gives up once `deadline` passes.
+fn run_to_completion(
+    mut sim: Simulation,
+    max_frames: usize,
+    deadline: Instant,
+) -> Result<(Vec<Value>, usize), String> {
+    let total_steps = (sim.simulation_time / sim.time_delta).floor() as usize;
+    let downsample = total_steps.div_ceil(max_frames).max(1);
+
+    let mut frames = Vec::with_capacity(total_steps / downsample);
+    for step in 1..=total_steps {
+        if Instant::now() > deadline {
+            return Err("Batch run timed out".into());
+        }
+        sim.calculate_step();
+        if step % downsample == 0 {
+            frames.push(json!(
+                sim.space_objects
+                    .iter()
+                    .enumerate()
+                    .map(|(i, obj)| position_json(i, obj, Vector2::zeros()))
+                    .collect::<Vec<_>>()
+            ));
+        }
+    }
+    Ok((frames, downsample))
+}
+
+/// Runs a `launch_simulation` payload to completion and returns the whole trajectory instead of
+/// streaming it.
+async fn run_batch(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    let (mut sim, _) = match parse_launch_payload(&data, state.max_objects) {
+        Ok(parsed) => parsed,
+        Err(e) => return simulation_error_response(e),
+    };
+    // Events are not part of the batch result, so don't let them pile up
+    sim.record_collisions = false;
+    let max_frames = data["max_frames"]
+        .as_u64()
+        .map_or(state.batch_max_frames, |n| n as usize)
+        .clamp(1, state.batch_max_frames);
+    let deadline = Instant::now() + state.batch_timeout;
+
+    match tokio::task::spawn_blocking(move || run_to_completion(sim, max_frames, deadline)).await {
+        Ok(Ok((frames, downsample))) => (
+            StatusCode::OK,
+            Json(json!({
+                "status": "success",
+                "downsample": downsample,
+                "frames": frames,
+            })),
+        ),
+        Ok(Err(msg)) => error_response(StatusCode::REQUEST_TIMEOUT, msg),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
 async fn delete_simulation(
     State(state): State<AppState>,
     Json(data): Json<Value>,
 ) -> impl IntoResponse {
     let user_id = data["user_id"].as_str().unwrap_or_default().to_string();
-    stop_execution_pool(&state, &user_id);
+    stop_execution_pool(&state, &user_id).await;
     Json(json!({ "status": "success" }))
 }
 
-fn simulate_loop(
-    user_id: String,
-    simulation: Arc<Mutex<Simulation>>,
-    stop_flag: Arc<AtomicBool>,
-    tx: broadcast::Sender<(String, String)>,
-) {
-    thread::spawn(move || {
-        let target_step_time = 1.0 / 60.0;
+async fn update_params(
+    State(state): State<AppState>,
+    Json(data): Json<Value>,
+) -> impl IntoResponse {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let mut pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get_mut(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+    pool.last_activity = Instant::now();
 
-        // Один раз берём sim для параметров
-        let (steps_per_emit, total_steps) = {
-            let sim = simulation.lock().unwrap();
-            let steps = (target_step_time / sim.time_delta).max(1.0).floor() as usize;
-            let total = (sim.simulation_time / sim.time_delta).floor() as usize;
-            (steps, total)
-        };
+    let result = pool.simulation.lock().unwrap().update_params(
+        data["time_delta"].as_f64(),
+        data["G"].as_f64(),
+        data["acceleration_rate"].as_f64(),
+        data["elasticity_coefficient"].as_f64(),
+        data["time_scale"].as_f64(),
+    );
+    match result {
+        Ok(()) => (StatusCode::OK, Json(json!({ "status": "success" }))),
+        Err(e) => simulation_error_response(e),
+    }
+}
 
-        let mut step_count = 0;
+/// Adds an object on a circular orbit around `central`, see `Simulation::add_orbiting_body`.
+/// `object` takes the fields of a `space_objects` entry, position and velocity are ignored.
+async fn add_orbit(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let mut object = data["object"].clone();
+    if !object.is_object() {
+        return error_response(StatusCode::BAD_REQUEST, "Object must be a JSON object");
+    }
+    if object["movement_type"].is_null() {
+        object["movement_type"] = json!(MovementType::Ordinary as i64);
+    }
+    let body = match parse_space_object(&object) {
+        Ok(body) => body,
+        Err(e) => return simulation_error_response(e),
+    };
+    let Some(central) = data["central"].as_u64() else {
+        return error_response(StatusCode::BAD_REQUEST, "Central must be an object index");
+    };
+    let Some(orbit_radius) = data["radius"].as_f64() else {
+        return error_response(StatusCode::BAD_REQUEST, "Radius must be a number");
+    };
 
-        while !stop_flag.load(Ordering::Relaxed) && step_count < total_steps {
-            let start = Instant::now();
+    let mut pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get_mut(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+    pool.last_activity = Instant::now();
 
-            for _ in 0..steps_per_emit {
-                if stop_flag.load(Ordering::Relaxed) || step_count >= total_steps {
-                    break;
-                }
+    let mut sim = pool.simulation.lock().unwrap();
+    if sim.space_objects.len() >= state.max_objects {
+        return simulation_error_response(SimulationError::TooManyObjects {
+            count: sim.space_objects.len() + 1,
+            max: state.max_objects,
+        });
+    }
+    let result = sim.add_orbiting_body(
+        central as usize,
+        body,
+        data["angle"].as_f64().unwrap_or(0.0),
+        orbit_radius,
+        data["clockwise"].as_bool().unwrap_or(false),
+    );
+    match result {
+        Ok(index) => {
+            let mut entry = object_json(&sim.space_objects[index]);
+            entry["index"] = json!(index);
+            (
+                StatusCode::OK,
+                Json(json!({ "status": "success", "object": entry })),
+            )
+        }
+        Err(e) => simulation_error_response(e),
+    }
+}
 
-                let mut sim = simulation.lock().unwrap();
-                sim.calculate_step();
-                step_count += 1;
-            }
+/// Adds a batch of objects to a running simulation, all or none of them.
+async fn add_objects(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let Some(raw_objs) = data["objects"].as_array() else {
+        return error_response(StatusCode::BAD_REQUEST, "Objects must be an array");
+    };
+    let objects = match raw_objs
+        .iter()
+        .map(parse_space_object)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(objects) => objects,
+        Err(e) => return simulation_error_response(e),
+    };
 
-            let snapshot = {
-                let sim = simulation.lock().unwrap();
-                let state = sim
-                    .space_objects
-                    .iter()
-                    .enumerate()
-                    .map(|(i, obj)| {
-                        json!({
-                            i.to_string(): {
-                                "x": obj.position.x,
-                                "y": obj.position.y,
-                                "radius": obj.radius,
+    let mut pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get_mut(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+    pool.last_activity = Instant::now();
+
+    let mut sim = pool.simulation.lock().unwrap();
+    if sim.space_objects.len() + objects.len() > state.max_objects {
+        return simulation_error_response(SimulationError::TooManyObjects {
+            count: sim.space_objects.len() + objects.len(),
+            max: state.max_objects,
+        });
+    }
+    match sim.add_objects(objects) {
+        Ok(count) => (
+            StatusCode::OK,
+            Json(json!({ "status": "success", "object_count": count })),
+        ),
+        Err(e) => simulation_error_response(e),
+    }
+}
+
+async fn rescale_velocities(
+    State(state): State<AppState>,
+    Json(data): Json<Value>,
+) -> impl IntoResponse {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let Some(target) = data["kinetic_energy"].as_f64() else {
+        return error_response(StatusCode::BAD_REQUEST, "Kinetic energy must be a number");
+    };
+
+    let mut pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get_mut(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+    pool.last_activity = Instant::now();
+
+    let result = pool.simulation.lock().unwrap().rescale_velocities(target);
+    match result {
+        Ok(factor) => (
+            StatusCode::OK,
+            Json(json!({ "status": "success", "scale_factor": factor })),
+        ),
+        Err(e) => simulation_error_response(e),
+    }
+}
+
+/// Moves a simulation into its center of mass frame, see `Simulation::move_to_com_frame`.
+async fn center_com(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let mut pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get_mut(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+    pool.last_activity = Instant::now();
+
+    let result = pool.simulation.lock().unwrap().move_to_com_frame();
+    match result {
+        Ok(()) => (StatusCode::OK, Json(json!({ "status": "success" }))),
+        Err(e) => simulation_error_response(e),
+    }
+}
+
+/// Index of the only object called `name`.
+fn find_object(sim: &Simulation, name: &str) -> Result<usize, (StatusCode, Json<Value>)> {
+    let mut matches = sim
+        .space_objects
+        .iter()
+        .enumerate()
+        .filter(|(_, obj)| obj.name == name);
+    let Some((index, _)) = matches.next() else {
+        return Err(error_response(StatusCode::NOT_FOUND, "Object not found"));
+    };
+    if matches.next().is_some() {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Object name is not unique",
+        ));
+    }
+    Ok(index)
+}
+
+/// Adds `impulse / mass` to the velocity of the object given by `index` or `name`, for flick
+/// gestures.
+async fn impulse(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let (Some(x), Some(y)) = (data["impulse"]["x"].as_f64(), data["impulse"]["y"].as_f64()) else {
+        return error_response(StatusCode::BAD_REQUEST, "Impulse must be a 2D vector");
+    };
+
+    let mut pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get_mut(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+    pool.last_activity = Instant::now();
+
+    let mut sim = pool.simulation.lock().unwrap();
+    let index = match requested_object(&sim, &data) {
+        Ok(index) => index,
+        Err(response) => return response,
+    };
+    match sim.apply_impulse(index, Vector2::new(x, y)) {
+        Ok(()) => {
+            let obj = &sim.space_objects[index];
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "status": "success",
+                    "velocity": { "x": obj.velocity.x, "y": obj.velocity.y },
+                })),
+            )
+        }
+        Err(e) => simulation_error_response(e),
+    }
+}
+
+/// Index of the object a request names by `index` or `name`.
+fn requested_object(sim: &Simulation, data: &Value) -> Result<usize, (StatusCode, Json<Value>)> {
+    match (data["index"].as_u64(), data["name"].as_str()) {
+        (Some(index), _) => Ok(index as usize),
+        (None, Some(name)) => find_object(sim, name),
+        (None, None) => Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Index or name is required",
+        )),
+    }
+}
+
+/// Holds an object in place until `/thaw_object`, see `Simulation::freeze`.
+async fn freeze_object(
+    State(state): State<AppState>,
+    Json(data): Json<Value>,
+) -> impl IntoResponse {
+    set_frozen(&state, &data, true)
+}
+
+/// Releases an object held by `/freeze_object` with the velocity it had when frozen.
+async fn thaw_object(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    set_frozen(&state, &data, false)
+}
+
+fn set_frozen(state: &AppState, data: &Value, frozen: bool) -> (StatusCode, Json<Value>) {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let mut pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get_mut(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+    pool.last_activity = Instant::now();
+
+    let mut sim = pool.simulation.lock().unwrap();
+    let index = match requested_object(&sim, data) {
+        Ok(index) => index,
+        Err(response) => return response,
+    };
+    let result = if frozen {
+        sim.freeze(index)
+    } else {
+        sim.thaw(index)
+    };
+    match result {
+        Ok(()) => {
+            let obj = &sim.space_objects[index];
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "status": "success",
+                    "velocity": { "x": obj.velocity.x, "y": obj.velocity.y },
+                })),
+            )
+        }
+        Err(e) => simulation_error_response(e),
+    }
+}
+
+/// Fast-forwards the simulation of `user_id` to `step` without real-time pacing. The frame after
+/// the jump is broadcast as soon as it is reached.
+async fn seek(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let Some(step) = data["step"].as_u64() else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "Step must be a non-negative integer",
+        );
+    };
+    let mut pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get_mut(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+    pool.last_activity = Instant::now();
+
+    let current = pool.step_count.load(Ordering::Relaxed);
+    if step < current {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("Cannot seek back from step {} to {}", current, step),
+        );
+    }
+    pool.seek_target.fetch_max(step, Ordering::Relaxed);
+    (StatusCode::OK, Json(json!({ "status": "success" })))
+}
+
+async fn pause(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    set_paused(&state, &data, true)
+}
+
+async fn resume(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    set_paused(&state, &data, false)
+}
+
+fn set_paused(state: &AppState, data: &Value, paused: bool) -> (StatusCode, Json<Value>) {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let mut pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get_mut(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+    pool.last_activity = Instant::now();
+
+    pool.paused.store(paused, Ordering::Relaxed);
+    (StatusCode::OK, Json(json!({ "status": "success" })))
+}
+
+/// Computes one step of a paused simulation and returns the objects afterwards, for stepping
+/// through a scene frame by frame. Events of the step are broadcast as usual.
+async fn step(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let mut pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get_mut(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+    pool.last_activity = Instant::now();
+    if !pool.paused.load(Ordering::Relaxed) {
+        return error_response(StatusCode::CONFLICT, "Simulation is not paused");
+    }
+
+    let mut sim = pool.simulation.lock().unwrap();
+    if sim.time >= sim.simulation_time {
+        return error_response(StatusCode::CONFLICT, "Simulation has finished");
+    }
+    let step_started = Instant::now();
+    sim.calculate_step();
+    state.metrics.steps_total.fetch_add(1, Ordering::Relaxed);
+    state
+        .metrics
+        .step_nanos_total
+        .fetch_add(step_started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    let step_count = pool.step_count.fetch_add(1, Ordering::Relaxed) + 1;
+    let events = sim.take_events();
+    for payload in event_payloads(&events, &sim.space_objects) {
+        let _ = pool.tx.send(text_message(&payload));
+    }
+    (
+        StatusCode::OK,
+        Json(json!({
+            "status": "success",
+            "step_count": step_count,
+            "time": sim.time,
+            "space_objects": sim.space_objects.iter().map(object_json).collect::<Vec<_>>(),
+        })),
+    )
+}
+
+/// Messages for the events of one step. All collisions of the step share one message, naming
+/// the bodies by their index among `objects`, or `null` for one no longer there.
+fn event_payloads(events: &[SimulationEvent], objects: &[SpaceObject]) -> Vec<Value> {
+    let index_of = |id: &u64| objects.iter().position(|obj| obj.id == *id);
+    let mut payloads = Vec::new();
+    let mut collisions = Vec::new();
+    for event in events {
+        match event {
+            SimulationEvent::ObjectEscaped { name } => payloads.push(json!({
+                "event": "object_escaped",
+                "data": { "name": name }
+            })),
+            SimulationEvent::ObjectDisrupted { name } => payloads.push(json!({
+                "event": "object_disrupted",
+                "data": { "name": name }
+            })),
+            SimulationEvent::ObjectShattered { name } => payloads.push(json!({
+                "event": "object_shattered",
+                "data": { "name": name }
+            })),
+            SimulationEvent::ObjectExpired { name } => payloads.push(json!({
+                "event": "object_expired",
+                "data": { "name": name }
+            })),
+            SimulationEvent::Collision {
+                first,
+                second,
+                normal_speed,
+                contact_point,
+            } => collisions.push(json!({
+                "first": index_of(first),
+                "second": index_of(second),
+                "normal_speed": normal_speed,
+                "x": contact_point.x,
+                "y": contact_point.y,
+            })),
+        }
+    }
+    if !collisions.is_empty() {
+        payloads.push(json!({
+            "event": "collision",
+            "data": collisions
+        }));
+    }
+    payloads
+}
+
+/// Entry of an `update_step` frame, with the position of `obj` relative to `origin`.
+fn position_json(index: usize, obj: &SpaceObject, origin: Vector2<f64>) -> Value {
+    let position = obj.position - origin;
+    json!({
+        index.to_string(): {
+            "x": position.x,
+            "y": position.y,
+            "radius": obj.radius,
+        }
+    })
+}
+
+/// Binary `update_step` frame: one 14-byte record per object, in index order, all fields
+/// little-endian:
+///
+/// | offset | type | field  |
+/// |--------|------|--------|
+/// | 0      | u16  | index  |
+/// | 2      | f32  | x      |
+/// | 6      | f32  | y      |
+/// | 10     | f32  | radius |
+///
+/// `None` when there are more objects than a u16 can index, JSON frames are sent instead then.
+fn binary_snapshot(sim: &Simulation, origin: Vector2<f64>) -> Option<Bytes> {
+    let mut buf = Vec::with_capacity(sim.space_objects.len() * 14);
+    for (i, obj) in sim.space_objects.iter().enumerate() {
+        let position = obj.position - origin;
+        buf.extend_from_slice(&u16::try_from(i).ok()?.to_le_bytes());
+        buf.extend_from_slice(&(position.x as f32).to_le_bytes());
+        buf.extend_from_slice(&(position.y as f32).to_le_bytes());
+        buf.extend_from_slice(&(obj.radius as f32).to_le_bytes());
+    }
+    Some(Bytes::from(buf))
+}
+
+/// Replaces every fractional number in `value` by the shortest one that rounds to the same `f32`,
+/// so that it serializes with at most 9 significant digits.
+fn reduce_precision(value: &mut Value) {
+    match value {
+        Value::Number(n) if n.is_f64() => {
+            if let Some(x) = n
+                .as_f64()
+                .and_then(|x| (x as f32).to_string().parse::<f64>().ok())
+            {
+                *value = json!(x);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(reduce_precision),
+        Value::Object(fields) => fields.values_mut().for_each(reduce_precision),
+        _ => {}
+    }
+}
+
+/// Longest a simulation emitting `on_change` goes without broadcasting a snapshot.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most steps a run of `simulate_loop` computes, towards a `/seek` target or at a high
+/// `physics_hz`. The rest continue on the following runs, so other simulations sharing the
+/// workers are not held up.
+const MAX_STEPS_PER_RUN: u64 = 10_000;
+
+/// `time_scale` below which a simulation is treated as paused. Scaling the frame interval down
+/// further would only run it ever faster.
+const PAUSE_TIME_SCALE: f64 = 1e-3;
+
+/// Range of `emit_hz` a launch may ask for. Slower rates overflow the frame interval, faster
+/// ones would keep a worker busy broadcasting.
+const EMIT_HZ_RANGE: RangeInclusive<f64> = 0.1..=1000.0;
+
+/// Most frames a launch's `history_length` may keep, each holds a copy of every object.
+const MAX_HISTORY_LENGTH: u64 = 1000;
+
+/// Launch options controlling what `simulate_loop` streams to the client.
+#[derive(Debug, Clone)]
+struct StreamSettings {
+    /// Snapshots broadcast per real second.
+    emit_hz: f64,
+    /// With `"emit_mode": "on_change"`, a snapshot is only broadcast once some object moved
+    /// farther than this since the previous one, or `HEARTBEAT_INTERVAL` passed.
+    change_threshold: Option<f64>,
+    /// `calculate_step` calls per real second. Each advances `time_delta` of simulated time, so
+    /// the simulation runs `physics_hz * time_delta` times faster than real time, while snapshots
+    /// still go out `emit_hz` times per second. Unset means `1 / time_delta`, real time, at a
+    /// whole number of steps per snapshot.
+    physics_hz: Option<f64>,
+    /// Send an `update_velocity` frame every this many `update_step` frames.
+    velocity_update_interval: Option<u64>,
+    /// Shared with the client's socket, which may switch formats while the simulation runs.
+    binary_format: Arc<AtomicBool>,
+    /// When set, JSON `update_step` frames only carry objects that moved farther than this since
+    /// they were last sent. Frames listing every object are flagged with `"full": true`.
+    delta_epsilon: Option<f64>,
+    /// Recently emitted frames, shared with the pool for `/export_csv`. The last 100 unless the
+    /// launch sets `history_length`.
+    history: Arc<Mutex<History>>,
+    /// Attach the scene's `bounds` to JSON `update_step` frames, for auto-zooming cameras.
+    emit_bounds: bool,
+    /// Include velocity and acceleration of each object in JSON `update_step` frames.
+    emit_vectors: bool,
+    /// Include the temperature of each object in JSON `update_step` frames.
+    emit_temperature: bool,
+    /// Emit positions relative to the center of mass (`"frame": "barycenter"`) instead of the
+    /// world origin, so a drifting system stays centered on screen.
+    barycentric: bool,
+    /// Round the numbers of JSON `update_step` frames to single precision (`"precision": "f32"`),
+    /// which shortens them considerably. Binary frames are always single precision.
+    single_precision: bool,
+    /// Recent positions attached to every JSON `update_step` entry under `"trail"`, so a
+    /// reconnecting client can draw paths right away.
+    trails: Option<Arc<Mutex<Trails>>>,
+    /// Button presses to apply at given steps, sorted by step. When set, control thrust is
+    /// measured in simulated rather than wall-clock time.
+    input_schedule: Option<VecDeque<ScheduledInput>>,
+    /// Steps computed so far, shared with the pool for `/seek`.
+    step_count: Arc<AtomicU64>,
+    /// Step requested by `/seek`, reached as fast as possible on the next run.
+    seek_target: Arc<AtomicU64>,
+    /// Set by `/pause`, the simulation then only advances through `/step`.
+    paused: Arc<AtomicBool>,
+    checkpointing: Option<Checkpointing>,
+    /// Real time after which the simulation is stopped with a `simulation_complete` event, even
+    /// if simulated time is left.
+    max_wall_time: Option<Duration>,
+}
+
+/// Periodic saving of a running simulation, see `AppState::state_dir`.
+#[derive(Debug, Clone)]
+struct Checkpointing {
+    path: PathBuf,
+    interval: Duration,
+    /// The launch payload, updated with the current state on every write.
+    payload: Value,
+}
+
+impl StreamSettings {
+    fn from_payload(data: &Value) -> Result<Self, SimulationError> {
+        let emit_hz = data["emit_hz"].as_f64().unwrap_or(60.0);
+        if !EMIT_HZ_RANGE.contains(&emit_hz) {
+            return Err(SimulationError::OutOfRange {
+                what: "Emit rate",
+                min: *EMIT_HZ_RANGE.start(),
+                max: *EMIT_HZ_RANGE.end(),
+            });
+        }
+        let change_threshold = match data["emit_mode"].as_str().unwrap_or("interval") {
+            "interval" => None,
+            "on_change" => Some(data["change_threshold"].as_f64().unwrap_or(1e-3)),
+            other => {
+                return Err(SimulationError::UnknownOption {
+                    what: "emit mode",
+                    value: other.to_owned(),
+                });
+            }
+        };
+        if change_threshold.is_some_and(|eps| !eps.is_finite() || eps < 0.0) {
+            return Err(SimulationError::NegativeValue("Change threshold"));
+        }
+        let physics_hz = data["physics_hz"].as_f64();
+        if physics_hz.is_some_and(|hz| !hz.is_finite() || hz <= 0.0) {
+            return Err(SimulationError::NonPositiveValue("Physics rate"));
+        }
+        let barycentric = match data["frame"].as_str().unwrap_or("world") {
+            "world" => false,
+            "barycenter" => true,
+            other => {
+                return Err(SimulationError::UnknownOption {
+                    what: "frame",
+                    value: other.to_owned(),
+                });
+            }
+        };
+        let single_precision = match data["precision"].as_str().unwrap_or("f64") {
+            "f64" => false,
+            "f32" => true,
+            other => {
+                return Err(SimulationError::UnknownOption {
+                    what: "precision",
+                    value: other.to_owned(),
+                });
+            }
+        };
+        let max_wall_seconds = data["max_wall_seconds"].as_f64();
+        if max_wall_seconds.is_some_and(|secs| !secs.is_finite() || secs <= 0.0) {
+            return Err(SimulationError::NonPositiveValue("Maximum wall time"));
+        }
+        let trail_length = data["trail_length"].as_u64().unwrap_or(100);
+        if trail_length == 0 {
+            return Err(SimulationError::NonPositiveValue("Trail length"));
+        }
+        let history_length = data["history_length"].as_u64().unwrap_or(100);
+        if !(1..=MAX_HISTORY_LENGTH).contains(&history_length) {
+            return Err(SimulationError::OutOfRange {
+                what: "History length",
+                min: 1.0,
+                max: MAX_HISTORY_LENGTH as f64,
+            });
+        }
+
+        Ok(Self {
+            emit_hz,
+            change_threshold,
+            physics_hz,
+            velocity_update_interval: data["velocity_update_interval"].as_u64().filter(|&n| n > 0),
+            binary_format: Arc::default(),
+            delta_epsilon: data["delta_updates"]
+                .as_bool()
+                .unwrap_or(false)
+                .then(|| data["delta_epsilon"].as_f64().unwrap_or(1e-9)),
+            history: Arc::new(Mutex::new(History::new(history_length as usize))),
+            emit_bounds: data["emit_bounds"].as_bool().unwrap_or(false),
+            emit_vectors: data["emit_vectors"].as_bool().unwrap_or(false),
+            emit_temperature: data["emit_temperature"].as_bool().unwrap_or(false),
+            barycentric,
+            single_precision,
+            trails: data["emit_trails"]
+                .as_bool()
+                .unwrap_or(false)
+                .then(|| Arc::new(Mutex::new(Trails::new(trail_length as usize)))),
+            input_schedule: None,
+            step_count: Arc::default(),
+            seek_target: Arc::default(),
+            paused: Arc::new(AtomicBool::new(data["paused"].as_bool().unwrap_or(false))),
+            checkpointing: None,
+            max_wall_time: max_wall_seconds.map(Duration::from_secs_f64),
+        })
+    }
+}
+
+/// Ring buffer of the last emitted frames, oldest first. Always keeps at least the latest one.
+#[derive(Debug)]
+pub struct History {
+    capacity: usize,
+    frames: VecDeque<(u64, Vec<SpaceObject>)>,
+}
+
+impl History {
+    fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        // Filled one frame per emission, most simulations never reach the capacity
+        Self {
+            capacity,
+            frames: VecDeque::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    fn push(&mut self, frame: u64, objects: Vec<SpaceObject>) {
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back((frame, objects));
+    }
+}
+
+/// The last `length` emitted positions of every object, oldest first. Keyed by `id`, as indices
+/// shift when objects are removed.
+#[derive(Debug)]
+pub struct Trails {
+    length: usize,
+    points: HashMap<u64, VecDeque<Vector2<f64>>>,
+}
+
+impl Trails {
+    fn new(length: usize) -> Self {
+        Self {
+            length,
+            points: HashMap::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    fn push(&mut self, objects: &[SpaceObject], origin: Vector2<f64>) {
+        let ids: HashSet<u64> = objects.iter().map(|obj| obj.id).collect();
+        self.points.retain(|id, _| ids.contains(id));
+        for obj in objects {
+            let trail = self.points.entry(obj.id).or_default();
+            if trail.len() == self.length {
+                trail.pop_front();
+            }
+            trail.push_back(obj.position - origin);
+        }
+    }
+
+    fn json(&self, id: u64) -> Value {
+        let trail = self.points.get(&id).into_iter().flatten();
+        json!(trail.map(|p| json!([p.x, p.y])).collect::<Vec<_>>())
+    }
+}
+
+#[derive(Deserialize)]
+struct UserQuery {
+    user_id: String,
+}
+
+async fn diagnostics(
+    State(state): State<AppState>,
+    Query(query): Query<UserQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    let sim = pool.simulation.lock().unwrap();
+    let com = sim.center_of_mass();
+    let com_velocity = sim.center_of_mass_velocity();
+    (
+        StatusCode::OK,
+        Json(json!({
+            "center_of_mass": { "x": com.x, "y": com.y },
+            "center_of_mass_velocity": { "x": com_velocity.x, "y": com_velocity.y },
+            "angular_momentum": sim.total_angular_momentum(),
+        })),
+    )
+}
+
+/// Parameters and progress of a simulation, for clients restoring their controls after a
+/// reconnect.
+async fn simulation_info(
+    State(state): State<AppState>,
+    Query(query): Query<UserQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    let sim = pool.simulation.lock().unwrap();
+    (
+        StatusCode::OK,
+        Json(json!({
+            "time_delta": sim.time_delta,
+            "G": sim.g,
+            "collision_type": sim.collision_type as i64,
+            "elasticity_coefficient": sim.elasticity_coefficient,
+            "acceleration_rate": sim.acceleration_rate,
+            "step_count": pool.step_count.load(Ordering::Relaxed),
+            "paused": pool.paused.load(Ordering::Relaxed),
+            "total_steps": pool.step_count.load(Ordering::Relaxed) + sim.remaining_steps(),
+        })),
+    )
+}
+
+/// The two objects closest to touching, for highlighting imminent collisions. No content with
+/// fewer than two objects.
+async fn closest_pair(
+    State(state): State<AppState>,
+    Query(query): Query<UserQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found").into_response();
+    };
+
+    let closest = pool.simulation.lock().unwrap().closest_pair();
+    match closest {
+        Some((first, second, gap)) => Json(json!({
+            "first": first,
+            "second": second,
+            "gap": gap,
+        }))
+        .into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+async fn energy_breakdown(
+    State(state): State<AppState>,
+    Query(query): Query<UserQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    let sim = pool.simulation.lock().unwrap();
+    let objects = sim
+        .space_objects
+        .iter()
+        .zip(sim.energy_breakdown())
+        .enumerate()
+        .map(|(i, (obj, (kinetic, potential)))| {
+            json!({
+                "index": i,
+                "name": obj.name,
+                "kinetic_energy": kinetic,
+                "potential_energy": potential,
+            })
+        })
+        .collect::<Vec<_>>();
+    (StatusCode::OK, Json(json!(objects)))
+}
+
+/// Clusters of gravitationally bound objects, see `Simulation::bound_groups`.
+async fn bound_groups(
+    State(state): State<AppState>,
+    Query(query): Query<UserQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    let sim = pool.simulation.lock().unwrap();
+    let groups = sim
+        .bound_groups()
+        .into_iter()
+        .map(|group| {
+            let names = group
+                .iter()
+                .map(|&i| sim.space_objects[i].name.as_str())
+                .collect::<Vec<_>>();
+            json!({ "indices": group, "names": names, "bound": group.len() > 1 })
+        })
+        .collect::<Vec<_>>();
+    (StatusCode::OK, Json(json!(groups)))
+}
+
+#[derive(Deserialize)]
+struct ContactQuery {
+    user_id: String,
+    k: f64,
+}
+
+/// Pairs of objects in or near contact, see `Simulation::contact_pairs`, for drawing connections
+/// between bodies.
+async fn contact_graph(
+    State(state): State<AppState>,
+    Query(query): Query<ContactQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    let pairs = pool.simulation.lock().unwrap().contact_pairs(query.k);
+    match pairs {
+        Ok(pairs) => {
+            let edges = pairs
+                .into_iter()
+                .map(|(first, second, gap)| json!({ "first": first, "second": second, "gap": gap }))
+                .collect::<Vec<_>>();
+            (StatusCode::OK, Json(json!(edges)))
+        }
+        Err(e) => simulation_error_response(e),
+    }
+}
+
+async fn get_state_vector(
+    State(state): State<AppState>,
+    Query(query): Query<UserQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    let state_vector = pool.simulation.lock().unwrap().state_vector();
+    (StatusCode::OK, Json(json!({ "state": state_vector })))
+}
+
+async fn set_state_vector(
+    State(state): State<AppState>,
+    Json(data): Json<Value>,
+) -> impl IntoResponse {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let Ok(state_vector) = serde_json::from_value::<Vec<f64>>(data["state"].clone()) else {
+        return error_response(StatusCode::BAD_REQUEST, "State must be an array of numbers");
+    };
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    match pool
+        .simulation
+        .lock()
+        .unwrap()
+        .set_state_vector(&state_vector)
+    {
+        Ok(()) => (StatusCode::OK, Json(json!({ "status": "success" }))),
+        Err(e) => simulation_error_response(e),
+    }
+}
+
+async fn last_collision_age(
+    State(state): State<AppState>,
+    Query(query): Query<UserQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    let sim = pool.simulation.lock().unwrap();
+    let ages = sim
+        .space_objects
+        .iter()
+        .enumerate()
+        .map(|(i, obj)| {
+            json!({
+                "index": i,
+                "name": obj.name,
+                "steps_since_collision": obj.steps_since_collision,
+            })
+        })
+        .collect::<Vec<_>>();
+    (StatusCode::OK, Json(json!(ages)))
+}
+
+async fn sample_field(State(state): State<AppState>, Json(data): Json<Value>) -> impl IntoResponse {
+    let user_id = data["user_id"].as_str().unwrap_or_default();
+    let points = data["points"]
+        .as_array()
+        .unwrap_or(&vec![])
+        .iter()
+        .map(|p| {
+            Vector2::new(
+                p["x"].as_f64().unwrap_or(0.0),
+                p["y"].as_f64().unwrap_or(0.0),
+            )
+        })
+        .collect::<Vec<_>>();
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    let sim = pool.simulation.lock().unwrap();
+    let field = points
+        .into_iter()
+        .map(|p| {
+            let a = sim.field_at(p);
+            json!({ "x": a.x, "y": a.y })
+        })
+        .collect::<Vec<_>>();
+    (StatusCode::OK, Json(json!({ "field": field })))
+}
+
+#[derive(Deserialize)]
+struct GroupQuery {
+    user_id: String,
+    group: String,
+}
+
+async fn group_state(
+    State(state): State<AppState>,
+    Query(query): Query<GroupQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    let sim = pool.simulation.lock().unwrap();
+    let objects = sim
+        .space_objects
+        .iter()
+        .enumerate()
+        .filter(|(_, obj)| obj.group.as_deref() == Some(query.group.as_str()))
+        .map(|(i, obj)| {
+            let mut entry = object_json(obj);
+            entry["index"] = json!(i);
+            entry
+        })
+        .collect::<Vec<_>>();
+    (StatusCode::OK, Json(json!(objects)))
+}
+
+#[derive(Deserialize)]
+struct ObjectQuery {
+    user_id: String,
+    name: String,
+}
+
+async fn object_state(
+    State(state): State<AppState>,
+    Query(query): Query<ObjectQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    let sim = pool.simulation.lock().unwrap();
+    let index = match find_object(&sim, &query.name) {
+        Ok(index) => index,
+        Err(response) => return response,
+    };
+
+    let obj = &sim.space_objects[index];
+    let mut entry = object_json(obj);
+    entry["index"] = json!(index);
+    entry["acceleration"] = json!({ "x": obj.acceleration.x, "y": obj.acceleration.y });
+    (StatusCode::OK, Json(entry))
+}
+
+#[derive(Deserialize)]
+struct OrbitQuery {
+    user_id: String,
+    body: usize,
+    central: usize,
+}
+
+/// Two-body orbit of `body` around `central`.
+async fn orbit(
+    State(state): State<AppState>,
+    Query(query): Query<OrbitQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    let elements = pool
+        .simulation
+        .lock()
+        .unwrap()
+        .orbital_elements(query.body, query.central);
+    match elements {
+        Ok(elements) => (
+            StatusCode::OK,
+            Json(json!({
+                "semi_major_axis": elements.semi_major_axis,
+                "eccentricity": elements.eccentricity,
+                "period": elements.period,
+            })),
+        ),
+        Err(e) => simulation_error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct PairQuery {
+    user_id: String,
+    first: usize,
+    second: usize,
+}
+
+/// Straight-line estimate of when two objects touch, `null` if they never do and zero if they
+/// already overlap.
+async fn time_to_collision(
+    State(state): State<AppState>,
+    Query(query): Query<PairQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found");
+    };
+
+    let time = pool
+        .simulation
+        .lock()
+        .unwrap()
+        .time_to_collision(query.first, query.second);
+    match time {
+        Ok(time) => (StatusCode::OK, Json(json!({ "time": time }))),
+        Err(e) => simulation_error_response(e),
+    }
+}
+
+/// Server load in the Prometheus text exposition format.
+async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let (active_pools, objects) = {
+        let pools = state.pools.lock().unwrap();
+        let objects = pools
+            .values()
+            .map(|pool| pool.simulation.lock().unwrap().space_objects.len())
+            .sum::<usize>();
+        (pools.len(), objects)
+    };
+    let steps = state.metrics.steps_total.load(Ordering::Relaxed);
+    let step_nanos = state.metrics.step_nanos_total.load(Ordering::Relaxed);
+    let average_step_seconds = if steps == 0 {
+        0.0
+    } else {
+        step_nanos as f64 / steps as f64 / 1e9
+    };
+
+    let body = format!(
+        "# HELP rustgravity_active_pools Running simulations.\n\
+         # TYPE rustgravity_active_pools gauge\n\
+         rustgravity_active_pools {}\n\
+         # HELP rustgravity_objects Objects across all running simulations.\n\
+         # TYPE rustgravity_objects gauge\n\
+         rustgravity_objects {}\n\
+         # HELP rustgravity_steps_total Simulation steps computed since startup.\n\
+         # TYPE rustgravity_steps_total counter\n\
+         rustgravity_steps_total {}\n\
+         # HELP rustgravity_step_seconds_average Mean wall-clock time of a simulation step.\n\
+         # TYPE rustgravity_step_seconds_average gauge\n\
+         rustgravity_step_seconds_average {}\n",
+        active_pools, objects, steps, average_step_seconds
+    );
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Frames kept in the pool's history as CSV, one row per object per frame. Conflict until the
+/// first frame is emitted.
+async fn export_csv(
+    State(state): State<AppState>,
+    Query(query): Query<UserQuery>,
+) -> impl IntoResponse {
+    let pools = state.pools.lock().unwrap();
+    let Some(pool) = pools.get(&query.user_id) else {
+        return error_response(StatusCode::NOT_FOUND, "Simulation not found").into_response();
+    };
+
+    let history = pool.history.lock().unwrap();
+    if history.frames.is_empty() {
+        return error_response(StatusCode::CONFLICT, "No frames have been emitted yet")
+            .into_response();
+    }
+    let mut csv = String::from("frame,index,name,x,y,vx,vy,mass,radius\n");
+    for (frame, objects) in &history.frames {
+        for (i, obj) in objects.iter().enumerate() {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{}\n",
+                frame,
+                i,
+                csv_field(&obj.name),
+                obj.position.x,
+                obj.position.y,
+                obj.velocity.x,
+                obj.velocity.y,
+                obj.mass,
+                obj.radius
+            ));
+        }
+    }
+    ([(header::CONTENT_TYPE, "text/csv")], csv).into_response()
+}
+
+/// Builds the scheduler task driving one simulation: each run advances one frame worth of steps
+/// and broadcasts a snapshot.
+fn simulate_loop(
+    user_id: String,
+    simulation: Arc<Mutex<Simulation>>,
+    started: Instant,
+    stop_flag: Arc<AtomicBool>,
+    tx: broadcast::Sender<Message>,
+    metrics: Arc<Metrics>,
+    mut settings: StreamSettings,
+) -> Task {
+    // Each run steps through one emit interval of simulated time, so the physics rate does not
+    // depend on how often snapshots are sent
+    let target_step_time = 1.0 / settings.emit_hz;
+    let (simulation_time, mut elapsed_time) = {
+        let sim = simulation.lock().unwrap();
+        // time_delta may be changed via /update_params, so progress is tracked in simulated
+        // time, starting from where a restored checkpoint left off
+        (sim.simulation_time, sim.time)
+    };
+    let mut frame_count: u64 = 0;
+    // Fractional steps carried over between runs when `physics_hz` is not a multiple of `emit_hz`
+    let mut owed_steps = 0.0;
+    let mut last_emitted: Vec<Vector2<f64>> = Vec::new();
+    let mut last_checkpoint = Instant::now();
+    let mut last_sent: Vec<Vector2<f64>> = Vec::new();
+    let mut last_sent_at = Instant::now();
+
+    Box::new(move || {
+        if stop_flag.load(Ordering::Relaxed) {
+            return None;
+        }
+        let time_scale = simulation.lock().unwrap().time_scale;
+        if settings.paused.load(Ordering::Relaxed) || time_scale < PAUSE_TIME_SCALE {
+            // `/step` may have advanced the simulation meanwhile
+            elapsed_time = simulation.lock().unwrap().time;
+            return Some(Duration::from_secs_f64(target_step_time));
+        }
+        let reason = if elapsed_time >= simulation_time {
+            Some("finished")
+        } else if settings
+            .max_wall_time
+            .is_some_and(|max_wall_time| started.elapsed() >= max_wall_time)
+        {
+            Some("timeout")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            // Finished simulations are not resumed
+            if let Some(checkpointing) = &settings.checkpointing {
+                remove_checkpoint(&checkpointing.path);
+            }
+            let _ = tx.send(text_message(&json!({
+                "event": "simulation_complete",
+                "data": { "reason": reason }
+            })));
+            return None;
+        }
+
+        let steps_per_emit = {
+            let mut sim = simulation.lock().unwrap();
+            if settings.input_schedule.is_none() {
+                sim.apply_control_impulse(started.elapsed().as_secs_f64());
+            }
+            match settings.physics_hz {
+                Some(physics_hz) => {
+                    owed_steps += physics_hz * target_step_time;
+                    let steps = owed_steps.floor().min(MAX_STEPS_PER_RUN as f64);
+                    // At most a run's worth is carried over, a rate the workers cannot keep up
+                    // with runs slower than asked rather than falling ever further behind
+                    owed_steps = (owed_steps - steps).min(MAX_STEPS_PER_RUN as f64);
+                    steps as u64
+                }
+                None => (target_step_time / sim.time_delta)
+                    .clamp(1.0, MAX_STEPS_PER_RUN as f64)
+                    .floor() as u64,
+            }
+        };
+        let steps = settings
+            .seek_target
+            .load(Ordering::Relaxed)
+            .saturating_sub(settings.step_count.load(Ordering::Relaxed))
+            .min(MAX_STEPS_PER_RUN)
+            .max(steps_per_emit);
+
+        for _ in 0..steps {
+            let step_count = settings.step_count.load(Ordering::Relaxed);
+            if stop_flag.load(Ordering::Relaxed) || elapsed_time >= simulation_time {
+                break;
+            }
+
+            let mut sim = simulation.lock().unwrap();
+            if let Some(schedule) = settings.input_schedule.as_mut() {
+                while let Some(input) = schedule.pop_front_if(|input| input.step <= step_count) {
+                    if let Some(ctrl) = sim.controllable_acceleration.as_mut() {
+                        ctrl.set(&input.direction, input.is_pressed, elapsed_time);
+                    }
+                }
+                sim.apply_control_impulse(elapsed_time);
+            }
+            let step_started = Instant::now();
+            sim.calculate_step();
+            metrics.steps_total.fetch_add(1, Ordering::Relaxed);
+            metrics
+                .step_nanos_total
+                .fetch_add(step_started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            elapsed_time += sim.time_delta;
+            settings.step_count.fetch_add(1, Ordering::Relaxed);
+            let events = sim.take_events();
+            for payload in event_payloads(&events, &sim.space_objects) {
+                let _ = tx.send(text_message(&payload));
+            }
+        }
+        // Stopped mid-run, the owner is gone or about to be
+        if stop_flag.load(Ordering::Relaxed) {
+            return None;
+        }
+        if let Some(checkpointing) = &settings.checkpointing
+            && last_checkpoint.elapsed() >= checkpointing.interval
+        {
+            last_checkpoint = Instant::now();
+            let step_count = settings.step_count.load(Ordering::Relaxed);
+            if let Err(e) = write_checkpoint(checkpointing, &simulation.lock().unwrap(), step_count)
+            {
+                warn!("could not checkpoint simulation of {}: {}", user_id, e);
+            }
+        }
+        // Seeking resumes as soon as the workers allow, the snapshot goes out once it is done
+        if settings.step_count.load(Ordering::Relaxed)
+            < settings.seek_target.load(Ordering::Relaxed)
+            && elapsed_time < simulation_time
+        {
+            return Some(Duration::ZERO);
+        }
+        let delay = Duration::from_secs_f64(target_step_time * time_scale);
+        if let Some(threshold) = settings.change_threshold {
+            let sim = simulation.lock().unwrap();
+            let changed = last_sent.len() != sim.space_objects.len()
+                || sim
+                    .space_objects
+                    .iter()
+                    .zip(&last_sent)
+                    .any(|(obj, last)| (obj.position - last).norm() > threshold);
+            if !changed && last_sent_at.elapsed() < HEARTBEAT_INTERVAL {
+                return Some(delay);
+            }
+            last_sent = sim.space_objects.iter().map(|obj| obj.position).collect();
+            last_sent_at = Instant::now();
+        }
+
+        let origin = {
+            let sim = simulation.lock().unwrap();
+            let origin = if settings.barycentric {
+                sim.center_of_mass()
+            } else {
+                Vector2::zeros()
+            };
+            if let Some(trails) = &settings.trails {
+                trails.lock().unwrap().push(&sim.space_objects, origin);
+            }
+            origin
+        };
+        let binary = if settings.binary_format.load(Ordering::Relaxed) {
+            binary_snapshot(&simulation.lock().unwrap(), origin)
+        } else {
+            None
+        };
+        let message = if let Some(bytes) = binary {
+            // The next JSON frame has nothing to be a delta against
+            last_emitted.clear();
+            Message::Binary(bytes)
+        } else {
+            let sim = simulation.lock().unwrap();
+            let trails = settings
+                .trails
+                .as_ref()
+                .map(|trails| trails.lock().unwrap());
+            let full =
+                settings.delta_epsilon.is_none() || last_emitted.len() != sim.space_objects.len();
+            last_emitted.resize(sim.space_objects.len(), Vector2::zeros());
+
+            let state = sim
+                .space_objects
+                .iter()
+                .zip(last_emitted.iter_mut())
+                .enumerate()
+                .filter(|(_, (obj, last))| {
+                    full || settings
+                        .delta_epsilon
+                        .is_some_and(|eps| (obj.position - origin - **last).norm() > eps)
+                })
+                .map(|(i, (obj, last))| {
+                    *last = obj.position - origin;
+                    let mut entry = position_json(i, obj, origin);
+                    if settings.emit_vectors {
+                        let fields = &mut entry[i.to_string()];
+                        fields["vx"] = json!(obj.velocity.x);
+                        fields["vy"] = json!(obj.velocity.y);
+                        fields["ax"] = json!(obj.acceleration.x);
+                        fields["ay"] = json!(obj.acceleration.y);
+                    }
+                    if settings.emit_temperature {
+                        entry[i.to_string()]["temperature"] = json!(obj.temperature);
+                    }
+                    if let Some(color) = &obj.color {
+                        entry[i.to_string()]["color"] = json!(color);
+                    }
+                    if let Some(shape) = &obj.shape {
+                        entry[i.to_string()]["shape"] = json!(shape);
+                    }
+                    if let Some(trails) = &trails {
+                        entry[i.to_string()]["trail"] = trails.json(obj.id);
+                    }
+                    entry
+                })
+                .collect::<Vec<_>>();
+
+            let mut payload = json!({
+                "event": "update_step",
+                "data": state
+            });
+            if settings.single_precision {
+                reduce_precision(&mut payload["data"]);
+            }
+            if settings.delta_epsilon.is_some() {
+                payload["full"] = json!(full);
+            }
+            if sim.length_unit.is_some() || sim.meters_per_unit.is_some() {
+                payload["units"] = json!({
+                    "length_unit": sim.length_unit,
+                    "meters_per_unit": sim.meters_per_unit,
+                });
+            }
+            if settings.emit_bounds {
+                let (center, radius) = sim.bounding_circle();
+                let center = center - origin;
+                payload["bounds"] = json!({
+                    "center": { "x": center.x, "y": center.y },
+                    "radius": radius,
+                });
+            }
+            text_message(&payload)
+        };
+
+        let _ = tx.send(message);
+
+        frame_count += 1;
+        settings.history.lock().unwrap().push(
+            frame_count,
+            simulation.lock().unwrap().space_objects.clone(),
+        );
+        if let Some(interval) = settings.velocity_update_interval
+            && frame_count.is_multiple_of(interval)
+        {
+            let velocities = {
+                let sim = simulation.lock().unwrap();
+                sim.space_objects
+                    .iter()
+                    .enumerate()
+                    .map(|(i, obj)| {
+                        json!({
+                            i.to_string(): {
+                                "vx": obj.velocity.x,
+                                "vy": obj.velocity.y,
+                                "ax": obj.acceleration.x,
+                                "ay": obj.acceleration.y,
                             }
                         })
                     })
-                    .collect::<Vec<_>>();
-                json!(state)
+                    .collect::<Vec<_>>()
             };
-
             let payload = json!({
-                "event": "update_step",
-                "data": snapshot
+                "event": "update_velocity",
+                "data": velocities
             });
+            let _ = tx.send(text_message(&payload));
+        }
 
-            let _ = tx.send((user_id.clone(), payload.to_string()));
+        Some(delay)
+    })
+}
 
-            if let Some(remaining) =
-                Duration::from_secs_f64(target_step_time).checked_sub(start.elapsed())
-            {
-                thread::sleep(remaining);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_state() -> AppState {
+        AppState {
+            pools: Arc::default(),
+            channels: Arc::default(),
+            scheduler: Arc::new(Scheduler::new(2)),
+            binary_formats: Arc::default(),
+            max_objects: 100,
+            batch_timeout: Duration::from_secs(5),
+            batch_max_frames: 100,
+            button_press_rate: 100.0,
+            metrics: Arc::default(),
+            state_dir: None,
+            checkpoint_interval: Duration::from_secs(10),
+            ping_interval: Duration::from_secs(30),
+            pong_timeout: Duration::from_secs(10),
+            max_wall_time: Duration::from_secs(60),
         }
-    });
+    }
+
+    /// Two bodies in the shape of a `launch_simulation` payload.
+    fn launch_payload(user_id: &str) -> Value {
+        json!({
+            "user_id": user_id,
+            "G": 1.0,
+            "time_delta": 0.01,
+            "simulation_time": 100.0,
+            "space_objects": [
+                { "mass": 1.0, "radius": 0.1, "movement_type": 0 },
+                {
+                    "mass": 0.001,
+                    "radius": 0.1,
+                    "position": { "x": 1.0, "y": 0.0 },
+                    "velocity": { "x": 0.0, "y": 1.0 },
+                    "movement_type": 1
+                }
+            ]
+        })
+    }
+
+    fn ordinary(position: [f64; 2], velocity: [f64; 2]) -> SpaceObject {
+        SpaceObject::new(
+            "Body",
+            1.0,
+            0.1,
+            Vector2::from(position),
+            Vector2::from(velocity),
+            MovementType::Ordinary,
+            0.0,
+        )
+        .unwrap()
+    }
+
+    /// Task streaming a simulation of `objects` launched with the stream options of `payload`,
+    /// and a receiver subscribed before its first run.
+    fn stream(
+        objects: Vec<SpaceObject>,
+        payload: Value,
+    ) -> (Task, broadcast::Receiver<Message>, Arc<Mutex<Simulation>>) {
+        stream_with(objects, StreamSettings::from_payload(&payload).unwrap())
+    }
+
+    fn stream_with(
+        objects: Vec<SpaceObject>,
+        settings: StreamSettings,
+    ) -> (Task, broadcast::Receiver<Message>, Arc<Mutex<Simulation>>) {
+        let simulation =
+            Simulation::new(objects, 0.01, 1000.0, 1.0, CollisionType::Elastic, 1.0, 1.0).unwrap();
+        let simulation = Arc::new(Mutex::new(simulation));
+        let (tx, rx) = broadcast::channel(64);
+        let task = simulate_loop(
+            "test".into(),
+            Arc::clone(&simulation),
+            Instant::now(),
+            Arc::default(),
+            tx,
+            Arc::default(),
+            settings,
+        );
+        (task, rx, simulation)
+    }
+
+    /// Text frames received so far, parsed.
+    fn frames(rx: &mut broadcast::Receiver<Message>) -> Vec<Value> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|msg| match msg {
+                Message::Text(text) => serde_json::from_str(text.as_str()).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn velocity_frames_follow_interval() {
+        let (mut task, mut rx, simulation) = stream(
+            vec![ordinary([0.0, 0.0], [2.0, -1.0])],
+            json!({ "velocity_update_interval": 3 }),
+        );
+        for _ in 0..6 {
+            task();
+        }
+
+        let frames = frames(&mut rx);
+        let events = frames
+            .iter()
+            .map(|frame| frame["event"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        let mut expected = vec!["update_step"; 3];
+        expected.push("update_velocity");
+        assert_eq!(events, [expected.clone(), expected].concat());
+
+        let velocity = simulation.lock().unwrap().space_objects[0].velocity;
+        for frame in frames.iter().filter(|f| f["event"] == "update_velocity") {
+            assert_eq!(frame["data"][0]["0"]["vx"], json!(velocity.x));
+            assert_eq!(frame["data"][0]["0"]["vy"], json!(velocity.y));
+        }
+        assert_eq!(velocity, Vector2::new(2.0, -1.0));
+    }
+
+    #[test]
+    fn binary_snapshot_needs_u16_indices() {
+        let (_, _, simulation) = stream(vec![ordinary([0.0, 0.0], [0.0, 0.0])], json!({}));
+        let mut sim = simulation.lock().unwrap();
+        let obj = sim.space_objects[0].clone();
+        sim.space_objects
+            .resize(usize::from(u16::MAX) + 1, obj.clone());
+        let bytes = binary_snapshot(&sim, Vector2::zeros()).unwrap();
+        assert_eq!(bytes.len(), sim.space_objects.len() * 14);
+        assert_eq!(bytes[bytes.len() - 14..][..2], u16::MAX.to_le_bytes());
+
+        sim.space_objects.push(obj);
+        assert!(binary_snapshot(&sim, Vector2::zeros()).is_none());
+    }
+
+    #[tokio::test]
+    async fn non_finite_payload_values_are_rejected() {
+        let state = test_state();
+        let (status, _) = launch(&state, &launch_payload("valid"), None, None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let mut missing_axis = launch_payload("axes");
+        missing_axis["space_objects"][1]["semi_axes"] = json!({ "x": 0.1, "y": null });
+        let mut missing_elasticity = launch_payload("schedule");
+        missing_elasticity["elasticity_schedule"] = json!([{ "time": 1.0 }]);
+        for payload in [missing_axis, missing_elasticity] {
+            let (status, Json(body)) = launch(&state, &payload, None, None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        }
+
+        // Out of range of f64, the closest JSON gets to an explicit infinity
+        let infinite = br#"{"user_id": "inf", "G": 1e400}"#;
+        let rejection = Json::<Value>::from_bytes(infinite).unwrap_err();
+        assert_eq!(rejection.status(), StatusCode::BAD_REQUEST);
+        stop_execution_pool(&state, "valid").await;
+    }
+
+    #[tokio::test]
+    async fn thrust_shares_the_button_press_limit() {
+        let state = test_state();
+        let mut payload = launch_payload("user");
+        payload["space_objects"][1]["movement_type"] = json!(2);
+        let (status, _) = launch(&state, &payload, None, None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let mut limiter = RateLimiter::new(2.0);
+        let binary_format = AtomicBool::new(false);
+        for x in [0.1, 0.2, 0.3, 0.4] {
+            let event = json!({ "event": "thrust", "data": { "x": x, "y": 0.0 } });
+            handle_client_event(&state, "user", &binary_format, &mut limiter, &event);
+        }
+        let thrust = {
+            let pools = state.pools.lock().unwrap();
+            let sim = pools["user"].simulation.lock().unwrap();
+            sim.controllable_acceleration.as_ref().unwrap().thrust()
+        };
+        assert_eq!(thrust, Vector2::new(0.2, 0.0));
+        stop_execution_pool(&state, "user").await;
+    }
+
+    #[test]
+    fn collisions_name_bodies_by_index_after_shattering() {
+        let heavy = SpaceObject::new(
+            "Heavy",
+            2.0,
+            0.1,
+            Vector2::new(0.0, 0.0),
+            Vector2::new(1.0, 0.0),
+            MovementType::Ordinary,
+            0.0,
+        )
+        .unwrap();
+        let objects = vec![heavy, ordinary([0.25, 0.0], [-1.0, 0.0])];
+        let mut sim = Simulation::new(objects, 0.01, 1000.0, 1.0, CollisionType::Elastic, 1.0, 1.0)
+            .unwrap()
+            .with_shatter(Some(0.5), 2)
+            .unwrap();
+        sim.record_collisions = true;
+        let mut events = Vec::new();
+        for _ in 0..10 {
+            sim.calculate_step();
+            events.extend(sim.take_events());
+        }
+
+        // The heavier body is gone, the lighter one moved down to index 0
+        assert_eq!(sim.space_objects[0].name, "Body");
+        let payloads = event_payloads(&events, &sim.space_objects);
+        let collision = payloads
+            .iter()
+            .find(|payload| payload["event"] == "collision")
+            .unwrap();
+        assert_eq!(collision["data"][0]["first"], Value::Null);
+        assert_eq!(collision["data"][0]["second"], json!(0));
+        assert!(
+            payloads
+                .iter()
+                .any(|payload| payload["event"] == "object_shattered")
+        );
+    }
+
+    #[tokio::test]
+    async fn nothing_is_broadcast_after_stop() {
+        let state = test_state();
+        let (tx, mut rx) = broadcast::channel(1024);
+        state.channels.lock().unwrap().insert("user".into(), tx);
+        let mut payload = launch_payload("user");
+        payload["emit_hz"] = json!(1000.0);
+        let (status, _) = launch(&state, &payload, None, None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert!(rx.recv().await.is_ok());
+        stop_execution_pool(&state, "user").await;
+        assert!(state.pools.lock().unwrap().is_empty());
+        while rx.try_recv().is_ok() {}
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(matches!(
+            rx.try_recv(),
+            Err(broadcast::error::TryRecvError::Empty)
+        ));
+    }
+
+    #[test]
+    fn seek_runs_in_chunks_and_emits_once_done() {
+        let settings = StreamSettings::from_payload(&json!({})).unwrap();
+        let (step_count, seek_target) = (
+            Arc::clone(&settings.step_count),
+            Arc::clone(&settings.seek_target),
+        );
+        let (mut task, mut rx, _) = stream_with(vec![ordinary([0.0, 0.0], [1.0, 0.0])], settings);
+        seek_target.store(25_000, Ordering::Relaxed);
+
+        for expected in [10_000, 20_000] {
+            assert_eq!(task(), Some(Duration::ZERO));
+            assert_eq!(step_count.load(Ordering::Relaxed), expected);
+            assert!(frames(&mut rx).is_empty());
+        }
+        assert_ne!(task(), Some(Duration::ZERO));
+        assert_eq!(step_count.load(Ordering::Relaxed), 25_000);
+        assert_eq!(frames(&mut rx).len(), 1);
+    }
+
+    #[test]
+    fn high_physics_rate_is_capped_per_run() {
+        let settings = StreamSettings::from_payload(&json!({ "physics_hz": 1e12 })).unwrap();
+        let step_count = Arc::clone(&settings.step_count);
+        let (mut task, mut rx, _) = stream_with(vec![ordinary([0.0, 0.0], [1.0, 0.0])], settings);
+
+        for expected in [10_000, 20_000] {
+            assert!(task().is_some_and(|delay| delay > Duration::ZERO));
+            assert_eq!(step_count.load(Ordering::Relaxed), expected);
+            assert_eq!(frames(&mut rx).len(), 1);
+        }
+    }
+
+    #[test]
+    fn near_zero_time_scale_pauses() {
+        let (mut task, mut rx, simulation) =
+            stream(vec![ordinary([0.0, 0.0], [1.0, 0.0])], json!({}));
+        simulation.lock().unwrap().time_scale = 1e-4;
+        for _ in 0..3 {
+            assert!(task().is_some_and(|delay| delay > Duration::ZERO));
+        }
+        assert_eq!(simulation.lock().unwrap().time, 0.0);
+        assert!(frames(&mut rx).is_empty());
+
+        simulation.lock().unwrap().time_scale = 1.0;
+        task();
+        assert!(simulation.lock().unwrap().time > 0.0);
+    }
+
+    #[tokio::test]
+    async fn only_restored_launches_resume_their_checkpoint() {
+        let state = test_state();
+        let mut payload = launch_payload("user");
+        payload["paused"] = json!(true);
+        payload["checkpoint"] = json!({ "time": 50.0, "step_count": 5000 });
+        let checkpoint = payload["checkpoint"].clone();
+
+        for (restored, time, step_count) in [(None, 0.0, 0), (Some(&checkpoint), 50.0, 5000)] {
+            let (status, _) = launch(&state, &payload, None, restored).await;
+            assert_eq!(status, StatusCode::OK);
+            let pools = state.pools.lock().unwrap();
+            let pool = &pools["user"];
+            assert_eq!(pool.simulation.lock().unwrap().time, time);
+            assert_eq!(pool.step_count.load(Ordering::Relaxed), step_count);
+        }
+        stop_execution_pool(&state, "user").await;
+    }
+
+    #[tokio::test]
+    async fn socket_resumes_simulation_without_one() {
+        let state = test_state();
+        let mut payload = launch_payload("restored");
+        payload["paused"] = json!(true);
+        let (status, _) = launch(&state, &payload, None, None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (user_id, mut rx, _) = attach_socket(&state, Some("restored"));
+        assert_eq!(user_id, "restored");
+        let tx = state.pools.lock().unwrap()["restored"].tx.clone();
+        tx.send(Message::Text("frame".into())).unwrap();
+        assert!(matches!(rx.try_recv(), Ok(Message::Text(text)) if text.as_str() == "frame"));
+
+        // Attached already, and unknown simulations cannot be taken over
+        for resume in ["restored", "unknown"] {
+            let (user_id, _, _) = attach_socket(&state, Some(resume));
+            assert_ne!(user_id, resume);
+        }
+        stop_execution_pool(&state, "restored").await;
+    }
+
+    #[test]
+    fn trails_follow_objects_sharing_a_name() {
+        let (mut task, mut rx, simulation) = stream(
+            vec![
+                ordinary([0.0, 0.0], [1.0, 0.0]),
+                ordinary([0.0, 5.0], [-1.0, 0.0]),
+            ],
+            json!({ "emit_trails": true, "trail_length": 10 }),
+        );
+        task();
+        task();
+        let frame = frames(&mut rx).pop().unwrap();
+        for (i, y) in [(0, 0.0), (1, 5.0)] {
+            let trail = frame["data"][i][i.to_string()]["trail"].as_array().unwrap();
+            assert_eq!(trail.len(), 2);
+            assert!(
+                trail
+                    .iter()
+                    .all(|p| (p[1].as_f64().unwrap() - y).abs() < 1.0)
+            );
+        }
+
+        // The second object moves up to index 0 and keeps its own trail
+        simulation.lock().unwrap().space_objects.remove(0);
+        task();
+        let frame = frames(&mut rx).pop().unwrap();
+        let trail = frame["data"][0]["0"]["trail"].as_array().unwrap();
+        assert_eq!(trail.len(), 3);
+        assert!(trail.iter().all(|p| p[1].as_f64().unwrap() > 4.0));
+    }
+
+    #[tokio::test]
+    async fn slow_socket_skips_to_the_oldest_retained_message() {
+        let (tx, mut rx) = broadcast::channel(4);
+        for i in 0..10 {
+            tx.send(Message::Text(i.to_string().into())).unwrap();
+        }
+        drop(tx);
+
+        let mut received = Vec::new();
+        while let Some(Message::Text(text)) = next_message(&mut rx, "slow").await {
+            received.push(text.as_str().to_owned());
+        }
+        assert_eq!(received, ["6", "7", "8", "9"]);
+    }
+
+    #[tokio::test]
+    async fn reset_replaces_the_task_of_a_live_pool() {
+        let state = test_state();
+        let (status, _) = launch(&state, &launch_payload("user"), None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let old_stop_flag = Arc::clone(&state.pools.lock().unwrap()["user"].stop_flag);
+
+        let response = reset(State(state.clone()), Json(json!({ "user_id": "user" })))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(old_stop_flag.load(Ordering::Relaxed));
+        {
+            let pools = state.pools.lock().unwrap();
+            assert!(!pools["user"].stop_flag.load(Ordering::Relaxed));
+            assert!(pools["user"].simulation.lock().unwrap().time < 100.0);
+        }
+
+        stop_execution_pool(&state, "user").await;
+    }
+
+    #[test]
+    fn emit_rate_outside_range_is_rejected() {
+        for emit_hz in [0.0, 1e-300, 0.05, 1001.0, 1e300, -1.0] {
+            let payload = json!({ "emit_hz": emit_hz });
+            let error = StreamSettings::from_payload(&payload).err().unwrap();
+            assert_eq!(error.code(), "out_of_range");
+        }
+        for emit_hz in [0.1, 60.0, 1000.0] {
+            assert!(StreamSettings::from_payload(&json!({ "emit_hz": emit_hz })).is_ok());
+        }
+    }
+
+    #[test]
+    fn history_keeps_recent_frames_by_default() {
+        let settings = StreamSettings::from_payload(&json!({})).unwrap();
+        assert_eq!(settings.history.lock().unwrap().capacity, 100);
+        let settings = StreamSettings::from_payload(&json!({ "history_length": 5 })).unwrap();
+        assert_eq!(settings.history.lock().unwrap().capacity, 5);
+        for history_length in [
+            json!(0),
+            json!(MAX_HISTORY_LENGTH + 1),
+            json!(1_000_000_000_000u64),
+        ] {
+            let payload = json!({ "history_length": history_length });
+            let error = StreamSettings::from_payload(&payload).err().unwrap();
+            assert_eq!(error.code(), "out_of_range");
+        }
+
+        let settings = StreamSettings::from_payload(&json!({})).unwrap();
+        let history = Arc::clone(&settings.history);
+        let (mut task, _, _) = stream_with(vec![ordinary([0.0, 0.0], [1.0, 0.0])], settings);
+        for _ in 0..3 {
+            task();
+        }
+        assert_eq!(history.lock().unwrap().frames.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn export_needs_an_emitted_frame() {
+        let state = test_state();
+        let mut payload = launch_payload("user");
+        payload["paused"] = json!(true);
+        let (status, _) = launch(&state, &payload, None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let query = || {
+            Query(UserQuery {
+                user_id: "user".into(),
+            })
+        };
+
+        let response = export_csv(State(state.clone()), query())
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let history = Arc::clone(&state.pools.lock().unwrap()["user"].history);
+        let sim = state.pools.lock().unwrap()["user"]
+            .simulation
+            .lock()
+            .unwrap()
+            .clone();
+        history.lock().unwrap().push(0, sim.space_objects);
+        let response = export_csv(State(state.clone()), query())
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        stop_execution_pool(&state, "user").await;
+    }
 }
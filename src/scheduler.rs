@@ -0,0 +1,236 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::error;
+
+/// A unit of work run repeatedly by the scheduler. Returning `Some(delay)` reschedules the task
+/// `delay` after its previous due time, `None` finishes it, and so does a panic.
+pub type Task = Box<dyn FnMut() -> Option<Duration> + Send>;
+
+struct Entry {
+    due: Instant,
+    seq: u64,
+    task: Task,
+    done: Arc<Completion>,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.due == other.due && self.seq == other.seq
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    // Reversed so that `BinaryHeap` pops the earliest due entry first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .due
+            .cmp(&self.due)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    entries: BinaryHeap<Entry>,
+    next_seq: u64,
+}
+
+impl Queue {
+    fn push(&mut self, due: Instant, task: Task, done: Arc<Completion>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.entries.push(Entry {
+            due,
+            seq,
+            task,
+            done,
+        });
+    }
+}
+
+#[derive(Default)]
+struct Completion {
+    finished: Mutex<bool>,
+    signal: Condvar,
+}
+
+impl Completion {
+    fn finish(&self) {
+        *self.finished.lock().unwrap() = true;
+        self.signal.notify_all();
+    }
+}
+
+pub struct TaskHandle {
+    done: Arc<Completion>,
+}
+
+impl TaskHandle {
+    /// Blocks until the scheduler has run the task for the last time.
+    pub fn join(&self) {
+        let mut finished = self.done.finished.lock().unwrap();
+        while !*finished {
+            finished = self.done.signal.wait(finished).unwrap();
+        }
+    }
+
+    /// Like `join`, but gives up after `timeout`. Returns whether the task finished.
+    pub fn join_timeout(&self, timeout: Duration) -> bool {
+        let finished = self.done.finished.lock().unwrap();
+        let (finished, _) = self
+            .done
+            .signal
+            .wait_timeout_while(finished, timeout, |finished| !*finished)
+            .unwrap();
+        *finished
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    available: Condvar,
+}
+
+/// Fixed-size worker pool time-slicing all submitted tasks. Tasks are run earliest-due first, so
+/// when the pool is overloaded every task still gets its turn, only at a reduced rate.
+pub struct Scheduler {
+    shared: Arc<Shared>,
+}
+
+impl Scheduler {
+    pub fn new(workers: usize) -> Self {
+        let shared = Arc::new(Shared::default());
+        for _ in 0..workers.max(1) {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || worker_loop(&shared));
+        }
+        Self { shared }
+    }
+
+    pub fn spawn(&self, task: Task) -> TaskHandle {
+        let done = Arc::new(Completion::default());
+        self.shared
+            .queue
+            .lock()
+            .unwrap()
+            .push(Instant::now(), task, Arc::clone(&done));
+        self.shared.available.notify_one();
+        TaskHandle { done }
+    }
+}
+
+pub fn default_worker_count() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+fn worker_loop(shared: &Shared) {
+    loop {
+        let Entry {
+            due,
+            mut task,
+            done,
+            ..
+        } = {
+            let mut queue = shared.queue.lock().unwrap();
+            loop {
+                let now = Instant::now();
+                match queue.entries.peek() {
+                    None => queue = shared.available.wait(queue).unwrap(),
+                    Some(entry) if entry.due <= now => break queue.entries.pop().unwrap(),
+                    Some(entry) => {
+                        let timeout = entry.due - now;
+                        queue = shared.available.wait_timeout(queue, timeout).unwrap().0;
+                    }
+                }
+            }
+        };
+
+        // A panicking task must neither take the worker down nor leave `join` waiting forever
+        match panic::catch_unwind(AssertUnwindSafe(&mut task)) {
+            Ok(Some(delay)) => {
+                let due = (due + delay).max(Instant::now());
+                shared.queue.lock().unwrap().push(due, task, done);
+                shared.available.notify_one();
+            }
+            Ok(None) => done.finish(),
+            Err(_) => {
+                error!("scheduled task panicked");
+                done.finish();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::atomic::{AtomicBool, Ordering::Relaxed},
+    };
+
+    use super::*;
+
+    #[test]
+    fn single_worker_runs_all_tasks_fairly() {
+        let scheduler = Scheduler::new(1);
+        let stop = Arc::new(AtomicBool::new(false));
+        let runs = Arc::new(Mutex::new([0u32; 3]));
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+
+        let handles = (0..3)
+            .map(|k| {
+                let (stop, runs, threads) =
+                    (Arc::clone(&stop), Arc::clone(&runs), Arc::clone(&threads));
+                scheduler.spawn(Box::new(move || {
+                    threads.lock().unwrap().insert(thread::current().id());
+                    runs.lock().unwrap()[k] += 1;
+                    thread::sleep(Duration::from_millis(1));
+                    (!stop.load(Relaxed)).then_some(Duration::ZERO)
+                }))
+            })
+            .collect::<Vec<_>>();
+        thread::sleep(Duration::from_millis(100));
+        stop.store(true, Relaxed);
+        for handle in &handles {
+            assert!(handle.join_timeout(Duration::from_secs(1)));
+        }
+
+        let runs = *runs.lock().unwrap();
+        let (min, max) = (runs.iter().min().unwrap(), runs.iter().max().unwrap());
+        assert!(*min >= 10, "every task progresses: {:?}", runs);
+        assert!(max - min <= 1, "tasks take turns: {:?}", runs);
+        assert_eq!(threads.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn panicking_task_finishes_and_worker_survives() {
+        let scheduler = Scheduler::new(1);
+        let panicking = scheduler.spawn(Box::new(|| panic!("task failure")));
+        assert!(panicking.join_timeout(Duration::from_secs(1)));
+
+        let ran = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&ran);
+        let next = scheduler.spawn(Box::new(move || {
+            flag.store(true, Relaxed);
+            None
+        }));
+        assert!(next.join_timeout(Duration::from_secs(1)));
+        assert!(ran.load(Relaxed));
+    }
+}